@@ -0,0 +1,111 @@
+//! Sourceable shell aliases that `cd` into each repository
+use std::fmt::{self, Display};
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+
+/// Shells `gitspace alias` and `gitspace completions` can generate scripts for
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub enum Shell {
+    Zsh,
+    Bash,
+    Fish,
+    Elvish,
+    Nushell,
+}
+
+impl Display for Shell {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Shell::Zsh => write!(f, "zsh"),
+            Shell::Bash => write!(f, "bash"),
+            Shell::Fish => write!(f, "fish"),
+            Shell::Elvish => write!(f, "elvish"),
+            Shell::Nushell => write!(f, "nushell"),
+        }
+    }
+}
+
+impl FromStr for Shell {
+    type Err = String;
+
+    fn from_str(shell: &str) -> Result<Self, Self::Err> {
+        match shell {
+            "zsh" => Ok(Shell::Zsh),
+            "bash" => Ok(Shell::Bash),
+            "fish" => Ok(Shell::Fish),
+            "elvish" => Ok(Shell::Elvish),
+            "nushell" | "nu" => Ok(Shell::Nushell),
+            _ => Err(format!(
+                "unsupported shell {} (use zsh, bash, fish, elvish or nushell)",
+                shell
+            )),
+        }
+    }
+}
+
+/// Whether `name` can be written into a script as is, ie. only uses `A-Z a-z 0-9 _ . -`
+pub fn is_valid_name(name: &str) -> bool {
+    !name.is_empty()
+        && name
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || matches!(c, '_' | '.' | '-'))
+}
+
+/// One line per `(alias, repo_dir)` that `cd`s into the directory, in `shell`'s syntax
+pub fn render(shell: Shell, aliases: &[(String, PathBuf)]) -> String {
+    let mut lines = vec![format!("# Generated by `gitspace alias --shell {}`", shell)];
+    lines.extend(aliases.iter().map(|(name, dir)| line(shell, name, dir)));
+    lines.join("\n") + "\n"
+}
+
+fn line(shell: Shell, name: &str, dir: &Path) -> String {
+    let dir = dir.to_string_lossy();
+    match shell {
+        Shell::Zsh | Shell::Bash => {
+            let command = format!("cd {}", posix_quote(&dir));
+            format!("alias {}={}", name, posix_quote(&command))
+        }
+        Shell::Fish => format!(
+            "function {}; cd '{}'; end",
+            name,
+            dir.replace('\\', "\\\\").replace('\'', "\\'")
+        ),
+        Shell::Elvish => format!("fn {} {{ cd '{}' }}", name, dir.replace('\'', "''")),
+        Shell::Nushell => format!(
+            "alias {} = cd \"{}\"",
+            name,
+            dir.replace('\\', "\\\\").replace('"', "\\\"")
+        ),
+    }
+}
+
+/// Single-quote `word` for sh-like shells
+fn posix_quote(word: &str) -> String {
+    format!("'{}'", word.replace('\'', r#"'\''"#))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn every_shell_gets_its_own_syntax() {
+        let aliases = vec![("gs-api".to_string(), PathBuf::from("/work/it's/api"))];
+        let line = |shell| render(shell, &aliases).lines().nth(1).unwrap().to_string();
+        assert_eq!(
+            line(Shell::Zsh),
+            r#"alias gs-api='cd '\''/work/it'\''\'\'''\''s/api'\'''"#
+        );
+        assert_eq!(line(Shell::Bash), line(Shell::Zsh));
+        assert_eq!(
+            line(Shell::Fish),
+            r"function gs-api; cd '/work/it\'s/api'; end"
+        );
+        assert_eq!(line(Shell::Elvish), "fn gs-api { cd '/work/it''s/api' }");
+        assert_eq!(
+            line(Shell::Nushell),
+            r#"alias gs-api = cd "/work/it's/api""#
+        );
+        assert_eq!("nu".parse(), Ok(Shell::Nushell));
+    }
+}
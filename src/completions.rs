@@ -0,0 +1,136 @@
+//! Shell completion scripts, with `--repo` values completed from the current workspace's config
+use clap::Command;
+use clap_complete::generate;
+use clap_complete_nushell::Nushell;
+
+use crate::alias::Shell;
+
+/// Lists the names `--repo` accepts; prints nothing useful outside a workspace, so errors are
+/// silenced
+const REPOS: &str = "gitspace repos 2>/dev/null";
+
+/// Completion script for `cmd` in `shell`'s syntax
+/// `-r/--repo` values are completed by running `gitspace repos` when the user presses tab
+pub fn render(shell: Shell, cmd: &mut Command) -> String {
+    let mut script = Vec::new();
+    match shell {
+        Shell::Zsh => generate(clap_complete::Shell::Zsh, cmd, "gitspace", &mut script),
+        Shell::Bash => generate(clap_complete::Shell::Bash, cmd, "gitspace", &mut script),
+        Shell::Fish => generate(clap_complete::Shell::Fish, cmd, "gitspace", &mut script),
+        Shell::Elvish => generate(clap_complete::Shell::Elvish, cmd, "gitspace", &mut script),
+        Shell::Nushell => generate(Nushell, cmd, "gitspace", &mut script),
+    }
+    let script = String::from_utf8(script).expect("completion scripts are utf-8");
+    match shell {
+        Shell::Zsh => zsh(&script),
+        Shell::Bash => bash(&script),
+        Shell::Fish => fish(&script),
+        Shell::Elvish => elvish(&script),
+        Shell::Nushell => nushell(&script),
+    }
+}
+
+fn zsh(script: &str) -> String {
+    let (compdef, rest) = script.split_once('\n').unwrap_or((script, ""));
+    let function = format!(
+        "_gitspace_repos() {{\n    local -a repos\n    repos=(${{(f)\"$({})\"}})\n    compadd -a repos\n}}\n",
+        REPOS
+    );
+    format!(
+        "{}\n\n{}{}",
+        compdef,
+        function,
+        rest.replace(":REPO:_default'", ":REPO:_gitspace_repos'")
+    )
+}
+
+fn bash(script: &str) -> String {
+    let mut lines = Vec::new();
+    let mut after_repo = false;
+    for line in script.lines() {
+        if after_repo {
+            let repos = format!(r#"compgen -W "$({})" -- "${{cur}}""#, REPOS);
+            lines.push(line.replace(r#"compgen -f "${cur}""#, &repos));
+        } else {
+            lines.push(line.to_string());
+        }
+        after_repo = matches!(line.trim(), "--repo)" | "-r)");
+    }
+    lines.join("\n") + "\n"
+}
+
+fn fish(script: &str) -> String {
+    script
+        .lines()
+        .map(|line| match line.contains(" -s r -l repo ") {
+            true => format!("{} -f -a \"({})\"", line, REPOS),
+            false => line.to_string(),
+        })
+        .collect::<Vec<_>>()
+        .join("\n")
+        + "\n"
+}
+
+fn elvish(script: &str) -> String {
+    format!(
+        "{}
+var gitspace-completer = $edit:completion:arg-completer[gitspace]
+set edit:completion:arg-completer[gitspace] = {{|@words|
+    if (and (> (count $words) 2) (has-value [-r --repo] $words[-2])) {{
+        try {{ {} }} catch {{ }}
+    }} else {{
+        $gitspace-completer $@words
+    }}
+}}
+",
+        script, REPOS
+    )
+}
+
+fn nushell(script: &str) -> String {
+    let completer = "  def \"nu-complete gitspace repos\" [] {\n    ^gitspace repos err> /dev/null | lines\n  }\n\n";
+    script
+        .replacen(
+            "module completions {\n\n",
+            &format!("module completions {{\n\n{}", completer),
+            1,
+        )
+        .replace(
+            "--repo(-r): string",
+            "--repo(-r): string@\"nu-complete gitspace repos\"",
+        )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use clap::{Arg, ArgAction};
+
+    #[test]
+    fn repo_values_are_completed_by_gitspace_repos() {
+        let command = || {
+            Command::new("gitspace").subcommand(
+                Command::new("exec").arg(
+                    Arg::new("repos")
+                        .short('r')
+                        .long("repo")
+                        .value_name("REPO")
+                        .action(ArgAction::Append),
+                ),
+            )
+        };
+        let script = |shell| render(shell, &mut command());
+
+        assert!(script(Shell::Zsh).contains(":REPO:_gitspace_repos'"));
+        assert!(!script(Shell::Zsh).contains(":REPO:_default'"));
+        assert!(script(Shell::Bash).contains(r#"compgen -W "$(gitspace repos 2>/dev/null)""#));
+        assert!(
+            script(Shell::Fish).contains(r#"-s r -l repo -r -f -a "(gitspace repos 2>/dev/null)""#)
+        );
+        assert!(script(Shell::Elvish).contains("has-value [-r --repo]"));
+        assert!(
+            script(Shell::Nushell).contains(r#"--repo(-r): string@"nu-complete gitspace repos""#)
+        );
+        assert!(script(Shell::Nushell).contains("def \"nu-complete gitspace repos\""));
+    }
+}
@@ -1,17 +1,32 @@
 // use std::convert::From;
 use dirs::home_dir;
-use git2::{build, Cred, FetchOptions, RemoteCallbacks};
+use schemars::JsonSchema;
 use serde::{Deserialize, Serialize};
 use serde_json::Value;
 use std::env::{current_dir, var};
-use std::fs::{create_dir_all, read_dir, remove_dir_all, remove_file, write, File};
-use std::io::prelude::*;
-use std::io::BufReader;
-use std::path::{Path, PathBuf};
-use symlink::{remove_symlink_dir, symlink_dir};
-
-const GITSPACE: &str = ".space";
-const CONFIG: &str = "config.json";
+use std::fs::{create_dir_all, read_to_string, remove_dir, remove_file, rename, write};
+use std::io;
+use std::path::{absolute, Component, Path, PathBuf};
+use std::thread::available_parallelism;
+use symlink::symlink_dir;
+
+use crate::alias;
+use crate::error::{GitspaceError, Result};
+use crate::format::{ConfigFormat, CONFIG_FILES};
+use crate::git::{self, Pin, SyncStatus};
+use crate::ignore;
+use crate::lock::{LockedRepo, Lockfile};
+use crate::pool;
+use crate::status::RepoStatus;
+use crate::symlinks::{
+    self, ConflictPolicy, LinkDrift, LinkMode, ManagedLink, SymlinkLayout, SymlinkManifest,
+    SymlinkReport, SymlinkStatus,
+};
+use crate::trash::{Trash, TrashEntry, TRASH};
+use crate::validate::{self, Problem};
+
+pub(crate) const GITSPACE: &str = ".space";
+pub(crate) const CONFIG: &str = "config.json";
 const REPOS: &str = "repositories";
 
 /// Refers to your ~/.ssh/config file
@@ -20,8 +35,8 @@ const REPOS: &str = "repositories";
 ///    HostName github.com
 ///    User git
 ///    IdentityFile ~/.ssh/id_rsa
-#[derive(Debug, PartialEq, Eq, Serialize, Deserialize, Clone)]
-#[serde(rename_all = "camelCase")]
+#[derive(Debug, PartialEq, Eq, Serialize, Deserialize, JsonSchema, Clone)]
+#[serde(rename_all = "camelCase", deny_unknown_fields)]
 pub struct Ssh {
     host: String,
     host_name: String,
@@ -29,46 +44,165 @@ pub struct Ssh {
     pub identity_file: String,
 }
 
-#[derive(Debug, PartialEq, Eq, Serialize, Deserialize, Clone)]
-#[serde(rename_all = "camelCase")]
+#[derive(Debug, PartialEq, Eq, Serialize, Deserialize, JsonSchema, Clone, Default)]
+#[serde(rename_all = "camelCase", deny_unknown_fields)]
 pub struct Repo {
     namespace: String,
     project: String,
-    // symlink: String,
-    // alias: String,
+    /// Branch to check out instead of the remote's default branch
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    branch: Option<String>,
+    /// Tag to check out; leaves HEAD detached
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    tag: Option<String>,
+    /// Commit (or any revspec) to check out; leaves HEAD detached
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    rev: Option<String>,
+    /// Where to link the repository, relative to the workspace root (eg. `services/billing`);
+    /// defaults to the path given by `symlinks.layout`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    symlink: Option<String>,
+    /// Name of the shell alias that `cd`s into the repository; defaults to the project name
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    alias: Option<String>,
+}
+
+impl Repo {
+    /// The ref this repository is pinned to; `rev` wins over `tag`, which wins over `branch`
+    pub fn pin(&self) -> Option<Pin> {
+        if let Some(rev) = &self.rev {
+            Some(Pin::Rev(rev.to_string()))
+        } else if let Some(tag) = &self.tag {
+            Some(Pin::Tag(tag.to_string()))
+        } else {
+            self.branch
+                .as_ref()
+                .map(|branch| Pin::Branch(branch.to_string()))
+        }
+    }
+
+    /// Whether `name` refers to this repository, either as `project` or `namespace/project`
+    pub fn matches(&self, name: &str) -> bool {
+        name == self.project || name == format!("{}/{}", self.namespace, self.project)
+    }
+
+    /// Path of this repository's link relative to the workspace root
+    /// only plain relative paths are allowed, so links can't escape the workspace
+    pub fn symlink_path(&self, layout: SymlinkLayout) -> Result<PathBuf> {
+        let path = match (&self.symlink, layout) {
+            (Some(symlink), _) => PathBuf::from(symlink),
+            (None, SymlinkLayout::Flat) => PathBuf::from(&self.project),
+            (None, SymlinkLayout::ByNamespace) => Path::new(&self.namespace).join(&self.project),
+        };
+        if path.components().next().is_some() && inside_workspace(&path) {
+            Ok(path)
+        } else {
+            Err(GitspaceError::Config(format!(
+                "symlink {:?} of {} must be a relative path inside the workspace",
+                path, self.project
+            )))
+        }
+    }
 }
 
-#[derive(Debug, PartialEq, Eq, Serialize, Deserialize, Clone)]
-#[serde(rename_all = "camelCase")]
+#[derive(Debug, PartialEq, Eq, Serialize, Deserialize, JsonSchema, Clone)]
+#[serde(rename_all = "camelCase", deny_unknown_fields)]
 pub struct Sync {
     enabled: bool,
     cron: String,
+    /// Number of repositories to clone/fetch at once; defaults to the number of CPUs
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    jobs: Option<usize>,
+}
+
+#[derive(Debug, PartialEq, Eq, Serialize, Deserialize, JsonSchema, Clone, Default)]
+#[serde(rename_all = "camelCase", deny_unknown_fields)]
+pub struct Symlinks {
+    /// Where links go for repositories without their own `symlink` path
+    #[serde(default)]
+    layout: SymlinkLayout,
+    /// Absolute or relative symlinks, or plain copies
+    #[serde(default)]
+    mode: LinkMode,
+    /// What `symlink` does when something other than the expected link is in the way
+    #[serde(default)]
+    on_conflict: ConflictPolicy,
+}
+
+#[derive(Debug, PartialEq, Eq, Serialize, Deserialize, JsonSchema, Clone, Default)]
+#[serde(rename_all = "camelCase", deny_unknown_fields)]
+pub struct Aliases {
+    /// Prepended to every alias name, eg. `gs-`
+    #[serde(default)]
+    prefix: String,
 }
 
-#[derive(Debug, PartialEq, Eq, Serialize, Deserialize, Clone)]
-#[serde(rename_all = "camelCase")]
+#[derive(Debug, PartialEq, Eq, Serialize, Deserialize, JsonSchema, Clone)]
+#[serde(rename_all = "camelCase", deny_unknown_fields)]
 pub struct Config {
     paths: Paths,
     pub ssh: Ssh,
     pub repositories: Vec<Repo>,
     sync: Sync,
+    #[serde(default)]
+    symlinks: Symlinks,
+    #[serde(default)]
+    aliases: Aliases,
+    /// Workspace root the paths above are resolved against; empty means the current directory
+    #[serde(skip)]
+    root: PathBuf,
 }
 
-#[derive(Debug, PartialEq, Eq, Serialize, Deserialize, Clone)]
-#[serde(rename_all = "camelCase")]
+#[derive(Debug, PartialEq, Eq, Serialize, Deserialize, JsonSchema, Clone)]
+#[serde(rename_all = "camelCase", deny_unknown_fields)]
 pub struct Paths {
     pub space: String,
     pub config: String,
     pub repositories: String,
+    /// Directory the links are created in, relative to the workspace root; defaults to the root
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub symlinks: Option<String>,
+    /// How clones are arranged inside the repositories directory
+    #[serde(default)]
+    pub layout: StorageLayout,
+}
+
+/// How clones are arranged inside the repositories directory
+#[derive(Debug, PartialEq, Eq, Serialize, Deserialize, JsonSchema, Clone, Copy, Default)]
+#[serde(rename_all = "kebab-case")]
+pub enum StorageLayout {
+    /// `<project>`
+    #[default]
+    Flat,
+    /// `<host>/<namespace>/<project>`, so repositories sharing a name can coexist
+    ByHost,
 }
 
-#[allow(dead_code)]
 pub enum PathType {
     Space,
     Config,
     Repositories,
     Key,
 }
+
+/// What a `clean` run did, for the caller to report
+#[derive(Debug, Default)]
+pub struct CleanReport {
+    /// Entry holding everything that was removed, for `gitspace restore`; `None` when nothing was
+    pub trashed: Option<TrashEntry>,
+    /// Paths left alone, each with the reason
+    pub kept: Vec<(PathBuf, String)>,
+}
+
+impl CleanReport {
+    fn trashed(entry: TrashEntry) -> CleanReport {
+        CleanReport {
+            trashed: Some(entry),
+            kept: Vec::new(),
+        }
+    }
+}
+
 pub fn cwd() -> String {
     if let Ok(current_dir) = current_dir() {
         if let Some(current_dir_str) = current_dir.to_str() {
@@ -88,24 +222,20 @@ impl Default for Paths {
             space: String::from(GITSPACE),
             config: String::from(CONFIG),
             repositories: String::from(REPOS),
+            symlinks: None,
+            layout: StorageLayout::Flat,
         }
     }
 }
 
 impl Default for Config {
     fn default() -> Self {
+        // Without a home directory, leave the usual path for the user to fill in
         let key_path = home_dir()
-            .unwrap()
-            .join(".ssh/id_rsa")
-            .to_str()
-            .unwrap()
-            .to_string();
+            .map(|home| home.join(".ssh/id_rsa").to_string_lossy().into_owned())
+            .unwrap_or_else(|| String::from("~/.ssh/id_rsa"));
         Config {
-            paths: Paths {
-                space: GITSPACE.to_string(),
-                config: CONFIG.to_string(),
-                repositories: REPOS.to_string(),
-            },
+            paths: Paths::default(),
             ssh: Ssh {
                 host: "github".to_string(),
                 host_name: "github.com".to_string(),
@@ -116,16 +246,22 @@ impl Default for Config {
                 Repo {
                     namespace: "capswan".to_string(),
                     project: "cli-gitspace".to_string(),
+                    ..Default::default()
                 },
                 Repo {
                     namespace: "capswan".to_string(),
                     project: "cli-ftr".to_string(),
+                    ..Default::default()
                 },
             ],
             sync: Sync {
                 enabled: true,
                 cron: "30 0 * * *".to_string(),
+                jobs: None,
             },
+            symlinks: Symlinks::default(),
+            aliases: Aliases::default(),
+            root: PathBuf::new(),
         }
     }
 }
@@ -133,27 +269,77 @@ impl Default for Config {
 // TODO: Create a getter that returns a tuple for config and repositories
 impl Config {
     /// Create a new .space directory with empty repositories directory
-    pub fn new() -> Self {
+    pub fn new() -> Result<Self> {
         let config = Self::default();
-        let repositories_path = &config.get_path_as_string(&PathType::Repositories);
-        create_dir_all(&repositories_path).unwrap();
-        config
+        config.create_layout()?;
+        Ok(config)
     }
 
-    /// Write config.json inside .space directory
-    pub fn write_config(&self) {
+    /// Create the .space and repositories directories; only `init` should call this
+    pub fn create_layout(&self) -> Result<()> {
+        let repositories_path = &self.get_path_as_string(&PathType::Repositories);
+        create_dir_all(repositories_path).map_err(GitspaceError::filesystem(repositories_path))
+    }
+
+    /// Write config.json inside .space directory, in the format `paths.config`'s extension asks for
+    pub fn write_config(&self) -> Result<()> {
         let config_path = &self.get_path_as_string(&PathType::Config);
         // println!("{:?}", config_path);
-        write(&config_path, &self.to_str()).unwrap();
+        let contents = ConfigFormat::from_path(Path::new(config_path))
+            .render(self)
+            .map_err(GitspaceError::config_parse(config_path))?;
+        write(config_path, contents).map_err(GitspaceError::config_io(config_path))
+    }
+
+    /// Keep the config in `.space/config.<format>` instead
+    pub fn with_format(self, format: ConfigFormat) -> Self {
+        Config {
+            paths: Paths {
+                config: format.file_name(),
+                ..self.paths
+            },
+            ..self
+        }
+    }
+
+    /// Anchor every workspace path to `root` instead of the current directory
+    /// a relative `root` is made absolute (with `..` folded away), so paths derived from it are
+    /// never anchored twice
+    pub fn with_root(self, root: impl Into<PathBuf>) -> Self {
+        let root = root.into();
+        let root = match root.as_os_str().is_empty() {
+            true => PathBuf::from(cwd()),
+            false => symlinks::normalize(&absolute(&root).unwrap_or(root)),
+        };
+        Config { root, ..self }
+    }
+
+    /// Directory containing .space, and the symlinks generated next to it
+    pub fn root(&self) -> PathBuf {
+        if self.root.as_os_str().is_empty() {
+            PathBuf::from(cwd())
+        } else {
+            self.root.to_path_buf()
+        }
     }
 
     /// Get common paths
     pub fn get_path_as_string(&self, path_type: &PathType) -> String {
-        let space_path = String::from(&self.paths.space);
+        let anchored = |path: String| {
+            if self.root.as_os_str().is_empty() {
+                path
+            } else {
+                self.root.join(path).to_string_lossy().to_string()
+            }
+        };
+        let space_path = anchored(String::from(&self.paths.space));
         // println!("{:?}", space_path);
-        let config_path = format!("{}/{}", &self.paths.space, &self.paths.config);
+        let config_path = anchored(format!("{}/{}", &self.paths.space, &self.paths.config));
         // println!("{:?}", config_path);
-        let repositories_path = format!("{}/{}", &self.paths.space, &self.paths.repositories);
+        let repositories_path = anchored(format!(
+            "{}/{}",
+            &self.paths.space, &self.paths.repositories
+        ));
         // println!("{:?}", repositories_path);
         let key_path = &self.ssh.identity_file;
         // println!("{:?}", key_path);
@@ -179,6 +365,7 @@ impl Config {
         )
     }
 
+    #[allow(dead_code)]
     fn exists(&self, path: &PathType) -> bool {
         let (space, config, repos, key) = &self.get_paths_as_strings();
         match path {
@@ -189,162 +376,913 @@ impl Config {
         }
     }
 
-    //TODO: Figure out why getting a no such file or directory when calling this from clone_repos
-    fn dir_is_empty(&self, path_type: &PathType, repo_dir: &str) -> bool {
-        println!("repo_dir {:?}", repo_dir);
-        let path = match path_type {
-            PathType::Space => self.get_path_as_string(&PathType::Space),
-            PathType::Config => self.get_path_as_string(&PathType::Config),
-            PathType::Repositories => self.get_path_as_string(&PathType::Repositories),
-            PathType::Key => self.get_path_as_string(&PathType::Key),
-        };
-        let repo_path = format!("{}/{}", path, repo_dir);
-        let repo_path = Path::new(&repo_path);
-        // println!("{:?}", repo_path);
-        if repo_path.exists() {
-            let mut entries = match repo_path.read_dir() {
-                Ok(entries) => entries,
-                Err(_) => return false,
-            };
-            entries.next().is_none()
-        } else {
-            false
-        }
-    }
-
-    /// Return a JSON value of the config.json file
+    // Return a JSON value of the config.json file
     // fn read_config_json(config_path: &Path) -> Value {
     //     // println!("{:?}", config_path);
-    //     let file = File::open(&config_path).unwrap();
+    //     let file = File::open(config_path).unwrap();
     //     let reader = BufReader::new(file);
     //     let value: Value = serde_json::from_reader(reader).unwrap();
     //     value
     // }
 
-    /// Return a Config struct of the .gitspace file
-    pub fn read_config_raw(config_path: &Path) -> Config {
+    /// Return a Config struct of the .gitspace file; JSON, YAML or TOML depending on its extension
+    pub fn read_config_raw(config_path: &Path) -> Result<Config> {
         // println!("{:?}", config_path);
-        let file = File::open(&config_path).unwrap();
-        let reader = BufReader::new(file);
-        let config: Config = serde_json::from_reader(reader).unwrap();
-        config
+        let contents =
+            read_to_string(config_path).map_err(GitspaceError::config_io(config_path))?;
+        let config: Config = ConfigFormat::from_path(config_path)
+            .parse(&contents)
+            .map_err(GitspaceError::config_parse(config_path))?;
+        // `paths.config` follows the file actually read, so `clean` finds a config.yaml too
+        match config_path.file_name().and_then(|name| name.to_str()) {
+            Some(name) if CONFIG_FILES.contains(&name) => Ok(Config {
+                paths: Paths {
+                    config: name.to_string(),
+                    ..config.paths
+                },
+                ..config
+            }),
+            _ => Ok(config),
+        }
     }
 
-    /// remove the .gitspace/config.json file
-    pub fn rm_config(&self) {
-        let config_path = &self.get_path_as_string(&PathType::Config);
-        remove_file(&config_path).unwrap();
-        println!("🧱 Removed config.json");
+    /// Trash that `clean` moves paths into, ie. `.space/trash`
+    pub fn trash(&self) -> Trash {
+        Trash::new(self.root(), Path::new(&self.paths.space).join(TRASH))
     }
 
-    /// remove the .gitspace/repositories directory
-    pub fn rm_repositories(&self) {
-        let repositories_path = &self.get_path_as_string(&PathType::Repositories);
-        remove_dir_all(&repositories_path).unwrap();
-        println!("🧱 Removed repositories directory");
-    }
-
-    /// create symlinks in cwd based on newly cloned repositories in ~/.space/repositories
-    /// return a vector of symlinks created
-    pub fn write_symlinks(repositories: &Vec<Repo>) -> Vec<(String, String)> {
-        //TODO: Add a check to see if the symlink already exists
-        let mut symlinks: Vec<(String, String)> = Vec::new();
-        repositories.iter().for_each(|repo| {
-            let (space_path, _, repos_path, _) = Config::default().get_paths_as_strings();
-            let project_src_path = format!("{}/{}", &repos_path, &repo.project);
-            let project_dest_path = format!("{}/{}", String::from(cwd()), &repo.project);
-
-            // println!("🧱 space_path: {}", &space_path);
-            // println!("🧱 project_src_path: {}", &project_src_path);
-            // println!("🧱 project_dest_path: {:#?}", &project_dest_path);
-            // println!("🧱 repos_path: {}", &repos_path);
-
-            symlink_dir(&project_src_path, &project_dest_path).unwrap();
-            let src_and_dest_paths = (project_src_path, project_dest_path);
-            // println!("🧱 src_and_dest_paths: {:?}", &src_and_dest_paths);
-            symlinks.push(src_and_dest_paths);
-        });
-        symlinks
+    /// Move `paths` into a new trash entry
+    pub(crate) fn move_to_trash(&self, target: &str, paths: &[PathBuf]) -> Result<TrashEntry> {
+        self.trash().put(target, paths)
+    }
+
+    /// move the .gitspace/config.json file to the trash
+    pub fn rm_config(&self) -> Result<CleanReport> {
+        let config_path = self.get_path_as_string(&PathType::Config);
+        let entry = self.move_to_trash(&self.paths.config, &[PathBuf::from(config_path)])?;
+        Ok(CleanReport::trashed(entry))
     }
 
-    pub fn rm_symlinks(&self) {
-        // let mut removed_symlinks: Vec<String> = Vec::new();
-        let entries = match Path::new(&cwd()).read_dir() {
-            Ok(entries) => entries,
-            Err(_) => return,
+    /// Every cloned repository whose removal would lose work, alongside what would be lost
+    /// directories that aren't git repositories are reported too, since nothing can vouch for them
+    pub fn unsaved_work(&self) -> Result<Vec<(String, Vec<String>)>> {
+        let repositories_path = PathBuf::from(self.get_path_as_string(&PathType::Repositories));
+        // Clones sit at `<host>/<namespace>/<project>` in the by-host layout
+        let depth = match self.paths.layout {
+            StorageLayout::Flat => 1,
+            StorageLayout::ByHost => 3,
         };
+        let mut repo_dirs = vec![repositories_path.to_path_buf()];
+        for _ in 0..depth {
+            let mut children = Vec::new();
+            for dir in repo_dirs.iter().filter(|dir| dir.is_dir()) {
+                let entries = dir.read_dir().map_err(GitspaceError::filesystem(dir))?;
+                for entry in entries {
+                    children.push(entry.map_err(GitspaceError::filesystem(dir))?.path());
+                }
+            }
+            repo_dirs = children;
+        }
+        let mut unsaved = Vec::new();
+        for repo_dir in repo_dirs {
+            let project = repo_dir
+                .strip_prefix(&repositories_path)
+                .unwrap_or(&repo_dir)
+                .to_string_lossy()
+                .to_string();
+            let reasons = match git::unsaved_work(&repo_dir) {
+                Ok(reasons) => reasons,
+                Err(e) => vec![format!("could not be inspected ({})", e.message())],
+            };
+            if !reasons.is_empty() {
+                unsaved.push((project, reasons));
+            }
+        }
+        unsaved.sort();
+        Ok(unsaved)
+    }
+
+    /// Refuse to go on when any cloned repository has unsaved work, unless forced
+    fn ensure_no_unsaved_work(&self, force: bool) -> Result<()> {
+        if force {
+            return Ok(());
+        }
+        let unsaved = self.unsaved_work()?;
+        if unsaved.is_empty() {
+            Ok(())
+        } else {
+            Err(GitspaceError::UnsavedWork(unsaved))
+        }
+    }
+
+    /// move the .gitspace/repositories directory to the trash
+    /// refuses when a repository has uncommitted changes, stashes, unpushed commits or
+    /// local-only branches, unless `force` is set
+    pub fn rm_repositories(&self, force: bool) -> Result<CleanReport> {
+        self.ensure_no_unsaved_work(force)?;
+        let repositories_path = self.get_path_as_string(&PathType::Repositories);
+        let entry = self.move_to_trash(
+            "repositories directory",
+            &[PathBuf::from(repositories_path)],
+        )?;
+        Ok(CleanReport::trashed(entry))
+    }
+
+    /// symlinks.json, recording which links in the workspace gitspace created
+    pub fn symlink_manifest_path(&self) -> PathBuf {
+        SymlinkManifest::path(Path::new(&self.get_path_as_string(&PathType::Space)))
+    }
+
+    /// Directory links are created in (`paths.symlinks`), relative to the workspace root
+    pub fn symlink_dir(&self) -> Result<PathBuf> {
+        let dir = PathBuf::from(self.paths.symlinks.as_deref().unwrap_or_default());
+        if inside_workspace(&dir) {
+            Ok(dir)
+        } else {
+            Err(GitspaceError::Config(format!(
+                "paths.symlinks {:?} must be a relative path inside the workspace",
+                dir
+            )))
+        }
+    }
+
+    /// Path of `repo`'s link relative to the workspace root: its `symlink` path, or the one
+    /// `symlinks.layout` gives it, inside `paths.symlinks`
+    pub fn link_path(&self, repo: &Repo) -> Result<PathBuf> {
+        Ok(self
+            .symlink_dir()?
+            .join(repo.symlink_path(self.symlinks.layout)?))
+    }
+
+    /// How `symlink` links repositories unless overridden on the command line
+    pub fn symlink_mode(&self) -> LinkMode {
+        self.symlinks.mode
+    }
+
+    /// Conflict policy `symlink` uses unless overridden on the command line
+    pub fn symlink_policy(&self) -> ConflictPolicy {
+        self.symlinks.on_conflict
+    }
+
+    /// create symlinks in the workspace root based on newly cloned repositories in .space/repositories
+    /// each link goes to the repository's `symlink` path or the `symlinks.layout` default,
+    /// creating intermediate directories as needed
+    /// links that already point at the right repository are kept (copies are refreshed when they
+    /// differ from the repository's checked-out commit, the old copy going to the trash); anything
+    /// else in the way is handled according to `on_conflict`, and every link is recorded in
+    /// symlinks.json
+    /// return each project's outcome, and the trash entries of whatever was moved out of the way
+    pub fn write_symlinks(
+        &self,
+        mode: LinkMode,
+        on_conflict: ConflictPolicy,
+    ) -> Result<SymlinkReport> {
+        let root = self.root();
+        let manifest_path = self.symlink_manifest_path();
+        let mut manifest = SymlinkManifest::read(&manifest_path)?;
+        let mut report = SymlinkReport::default();
+        let mut first_conflict = None;
+        for repo in &self.repositories {
+            let link = self.link_path(repo)?;
+            let project_src_path = self.repo_dir(repo);
+            let project_dest_path = root.join(&link);
+            if let Some(parent) = project_dest_path.parent() {
+                create_dir_all(parent).map_err(GitspaceError::filesystem(parent))?;
+            }
+            let our_copy = manifest
+                .get(&link)
+                .is_some_and(|managed| managed.mode == LinkMode::Copy);
+            let status = self.link_repo(
+                &project_src_path,
+                &project_dest_path,
+                mode,
+                on_conflict,
+                our_copy,
+                &mut report.trashed,
+            )?;
+            if let SymlinkStatus::Conflict(_) = status {
+                first_conflict.get_or_insert(project_dest_path);
+            } else {
+                manifest.insert(ManagedLink {
+                    project: repo.project.to_string(),
+                    link,
+                    mode,
+                });
+                // Record every link as soon as it exists so a later failure can't orphan it
+                manifest.write(&manifest_path)?;
+            }
+            report.links.push((repo.project.to_string(), status));
+        }
+
+        match first_conflict {
+            Some(dest) if on_conflict == ConflictPolicy::Error => {
+                let in_the_way = io::Error::new(
+                    io::ErrorKind::AlreadyExists,
+                    "in the way of a symlink (use --on-conflict skip, replace or backup)",
+                );
+                Err(GitspaceError::filesystem(dest)(in_the_way))
+            }
+            _ => Ok(report),
+        }
+    }
+
+    /// Point `dest` at `src`, applying `on_conflict` when something else is already at `dest`
+    /// gitspace's own links and copies (`our_copy`) are switched to `mode` without asking
+    /// anything moved to the trash on the way is added to `trashed`
+    fn link_repo(
+        &self,
+        src: &Path,
+        dest: &Path,
+        mode: LinkMode,
+        on_conflict: ConflictPolicy,
+        our_copy: bool,
+        trashed: &mut Vec<TrashEntry>,
+    ) -> Result<SymlinkStatus> {
+        let target = match mode {
+            LinkMode::Relative => symlinks::relative_target(src, dest),
+            LinkMode::Absolute | LinkMode::Copy => src.to_path_buf(),
+        };
+        let in_the_way = match (symlinks::resolve(dest), dest.symlink_metadata()) {
+            (None, Err(_)) => {
+                materialize(src, dest, &target, mode)?;
+                return Ok(SymlinkStatus::Created);
+            }
+            (Some(_), _)
+                if mode != LinkMode::Copy
+                    && dest.read_link().ok() == Some(target.to_path_buf()) =>
+            {
+                return Ok(SymlinkStatus::Unchanged)
+            }
+            (Some(resolved), _) if resolved == src => {
+                remove_file(dest).map_err(GitspaceError::filesystem(dest))?;
+                materialize(src, dest, &target, mode)?;
+                return Ok(SymlinkStatus::Replaced);
+            }
+            (None, Ok(metadata))
+                if our_copy
+                    && mode == LinkMode::Copy
+                    && metadata.is_dir()
+                    && git::matches_export(src, dest)? =>
+            {
+                return Ok(SymlinkStatus::Unchanged)
+            }
+            (None, Ok(metadata)) if our_copy && metadata.is_dir() => {
+                // The copy may have been edited, so it's trashed rather than deleted
+                trashed.push(self.move_to_trash("refreshed copy", &[dest.to_path_buf()])?);
+                materialize(src, dest, &target, mode)?;
+                return Ok(match mode {
+                    LinkMode::Copy => SymlinkStatus::Refreshed,
+                    LinkMode::Absolute | LinkMode::Relative => SymlinkStatus::Replaced,
+                });
+            }
+            (Some(resolved), _) => format!("symlink to {:?}", resolved),
+            (None, Ok(metadata)) if metadata.is_dir() => "directory in the way".to_string(),
+            (None, Ok(_)) => "file in the way".to_string(),
+        };
+        let status = match on_conflict {
+            ConflictPolicy::Error | ConflictPolicy::Skip => {
+                return Ok(SymlinkStatus::Conflict(in_the_way))
+            }
+            ConflictPolicy::Replace if dest.is_symlink() => {
+                remove_file(dest).map_err(GitspaceError::filesystem(dest))?;
+                SymlinkStatus::Replaced
+            }
+            ConflictPolicy::Replace => {
+                // Real files and directories are never deleted outright
+                trashed.push(self.move_to_trash("replaced path", &[dest.to_path_buf()])?);
+                SymlinkStatus::Replaced
+            }
+            ConflictPolicy::Backup => {
+                let backup = backup_path(dest);
+                rename(dest, &backup).map_err(GitspaceError::filesystem(dest))?;
+                SymlinkStatus::BackedUp(backup)
+            }
+        };
+        materialize(src, dest, &target, mode)?;
+        Ok(status)
+    }
+
+    /// Shell alias name for every repository (`aliases.prefix` plus its `alias` or project name),
+    /// alongside the directory it's cloned into
+    /// fails on names that aren't safe to write into a shell script
+    pub fn aliases(&self) -> Result<Vec<(String, PathBuf)>> {
+        self.repositories
+            .iter()
+            .map(|repo| {
+                let name = format!("{}{}", self.aliases.prefix, alias_name(repo));
+                if !alias::is_valid_name(&name) {
+                    return Err(GitspaceError::Config(format!(
+                        "alias {:?} can only contain letters, digits, _, . and -",
+                        name
+                    )));
+                }
+                Ok((name, self.repo_dir(repo)))
+            })
+            .collect()
+    }
+
+    /// .gitignore patterns for everything gitspace generates: clones, the trash and every link
+    pub fn gitignore_entries(&self) -> Result<Vec<String>> {
+        let pattern = |path: &Path| {
+            let components: Vec<String> = path
+                .components()
+                .map(|component| component.as_os_str().to_string_lossy().to_string())
+                .collect();
+            format!("/{}", components.join("/"))
+        };
+        let space = Path::new(&self.paths.space);
+        let mut entries = vec![
+            format!("{}/", pattern(&space.join(&self.paths.repositories))),
+            format!("{}/", pattern(&space.join(TRASH))),
+        ];
+        for repo in &self.repositories {
+            entries.push(pattern(&self.link_path(repo)?));
+        }
+        Ok(entries)
+    }
+
+    /// Update the managed block of the workspace .gitignore
+    /// return its path when anything changed
+    pub fn write_gitignore(&self) -> Result<Option<PathBuf>> {
+        let gitignore_path = self.root().join(".gitignore");
+        let changed = ignore::write_block(&gitignore_path, &self.gitignore_entries()?)?;
+        Ok(changed.then_some(gitignore_path))
+    }
+
+    /// Compare the links on disk with the ones config.json expects in `mode`
+    /// return every drifted link, keyed by its path relative to the workspace root
+    pub fn check_symlinks(&self, mode: LinkMode) -> Result<Vec<(String, LinkDrift)>> {
+        let root = self.root();
+        let manifest = SymlinkManifest::read(&self.symlink_manifest_path())?;
+        let mut drift = Vec::new();
+        let mut expected = Vec::new();
+        for repo in &self.repositories {
+            let link = self.link_path(repo)?;
+            let src = self.repo_dir(repo);
+            let dest = root.join(&link);
+            let our_copy = manifest
+                .get(&link)
+                .is_some_and(|managed| managed.mode == LinkMode::Copy);
+            let found = match (symlinks::resolve(&dest), dest.symlink_metadata()) {
+                (None, Err(_)) => Some(LinkDrift::Missing),
+                (Some(target), _) if !target.exists() => Some(LinkDrift::Dangling(target)),
+                (Some(target), _) if target != src => Some(LinkDrift::PointsElsewhere(target)),
+                (Some(_), _) if mode == LinkMode::Copy => {
+                    Some(LinkDrift::InTheWay("symlink instead of a copy".to_string()))
+                }
+                (Some(_), _) => None,
+                (None, Ok(metadata)) if mode == LinkMode::Copy && our_copy && metadata.is_dir() => {
+                    // A repository that isn't cloned can't be compared; `sync` reports that
+                    match git::matches_export(&src, &dest) {
+                        Ok(false) => Some(LinkDrift::Outdated),
+                        Ok(true) | Err(_) => None,
+                    }
+                }
+                (None, Ok(metadata)) if metadata.is_dir() => {
+                    Some(LinkDrift::InTheWay("directory".to_string()))
+                }
+                (None, Ok(_)) => Some(LinkDrift::InTheWay("file".to_string())),
+            };
+            if let Some(found) = found {
+                drift.push((link.to_string_lossy().to_string(), found));
+            }
+            expected.push(link);
+        }
+        for managed in &manifest.links {
+            let dest = root.join(&managed.link);
+            if !expected.contains(&managed.link) && dest.symlink_metadata().is_ok() {
+                drift.push((managed.link.to_string_lossy().to_string(), LinkDrift::Extra));
+            }
+        }
+        Ok(drift)
+    }
+
+    /// Fix the drift `check_symlinks` finds: stray links symlinks.json owns are removed, extra
+    /// ones trashed, and missing ones created; anything else in the way, including links
+    /// gitspace didn't create, is handled according to `on_conflict`
+    /// return what was removed, trashed and linked; `check_symlinks` finds any drift that's left
+    pub fn repair_symlinks(
+        &self,
+        mode: LinkMode,
+        on_conflict: ConflictPolicy,
+    ) -> Result<SymlinkReport> {
+        let root = self.root();
+        let manifest_path = self.symlink_manifest_path();
+        let mut manifest = SymlinkManifest::read(&manifest_path)?;
+        let mut removed = Vec::new();
+        let mut extra = Vec::new();
+        for (link, drift) in self.check_symlinks(mode)? {
+            let dest = root.join(&link);
+            match drift {
+                LinkDrift::Dangling(_) | LinkDrift::PointsElsewhere(_)
+                    if manifest.contains(Path::new(&link)) =>
+                {
+                    remove_file(&dest).map_err(GitspaceError::filesystem(&dest))?;
+                    removed.push(dest);
+                }
+                LinkDrift::Extra => extra.push(dest),
+                _ => {}
+            }
+        }
+        let mut trashed = Vec::new();
+        if !extra.is_empty() {
+            trashed.push(self.move_to_trash("extra symlinks", &extra)?);
+            manifest
+                .links
+                .retain(|managed| !extra.contains(&root.join(&managed.link)));
+            manifest.write(&manifest_path)?;
+        }
+        // Conflicts are left as drift for `check_symlinks` rather than failing the repair
+        let on_conflict = match on_conflict {
+            ConflictPolicy::Error => ConflictPolicy::Skip,
+            policy => policy,
+        };
+        let mut report = self.write_symlinks(mode, on_conflict)?;
+        report.removed = removed;
+        trashed.append(&mut report.trashed);
+        report.trashed = trashed;
+        Ok(report)
+    }
+
+    /// move the symlinks gitspace created to the trash, along with symlinks.json
+    /// recorded links that are gone (stale) or no longer point into the repositories directory,
+    /// and links gitspace never created (foreign), are reported and left alone
+    pub fn rm_symlinks(&self) -> Result<CleanReport> {
+        let root = self.root();
+        let manifest_path = self.symlink_manifest_path();
+        let manifest = SymlinkManifest::read(&manifest_path)?;
+        let repositories_path = PathBuf::from(self.get_path_as_string(&PathType::Repositories));
+
+        let mut report = CleanReport::default();
+        let mut owned = Vec::new();
+        for managed in &manifest.links {
+            let link = root.join(&managed.link);
+            let is_copy = !link.is_symlink() && link.is_dir();
+            let reason = match symlinks::resolve(&link) {
+                None if managed.mode == LinkMode::Copy && is_copy => {
+                    owned.push(link);
+                    continue;
+                }
+                Some(target) if target.starts_with(&repositories_path) => {
+                    owned.push(link);
+                    continue;
+                }
+                Some(target) => format!("it now points to {:?}", target),
+                None if link.symlink_metadata().is_ok() => "it is no longer a symlink".to_string(),
+                None => "it was already removed".to_string(),
+            };
+            report.kept.push((link, reason));
+        }
+
+        let symlink_dir = root.join(self.symlink_dir()?);
+        if symlink_dir.is_dir() {
+            let entries = symlink_dir
+                .read_dir()
+                .map_err(GitspaceError::filesystem(&symlink_dir))?;
+            for entry in entries {
+                let path = entry
+                    .map_err(GitspaceError::filesystem(&symlink_dir))?
+                    .path();
+                let relative = path.strip_prefix(&root).unwrap_or(&path);
+                if path.is_symlink() && !manifest.contains(relative) {
+                    let reason = "gitspace didn't create it".to_string();
+                    report.kept.push((path, reason));
+                }
+            }
+        }
+
+        // The manifest goes along with the links so restoring them restores their ownership too
+        if manifest_path.exists() {
+            owned.push(manifest_path);
+        }
+        if !owned.is_empty() {
+            report.trashed = Some(self.move_to_trash("symlinks", &owned)?);
+        }
+        Ok(report)
+    }
+
+    /// move everything in the .gitspace directory, including the repositories, to the trash
+    /// the trash itself stays behind so the workspace can be restored
+    /// refuses when a repository has unsaved work, unless `force` is set
+    pub fn rm_space(&self, force: bool) -> Result<CleanReport> {
+        self.ensure_no_unsaved_work(force)?;
+        let space_path = self.get_path_as_string(&PathType::Space);
+        let entries = Path::new(&space_path)
+            .read_dir()
+            .map_err(GitspaceError::filesystem(&space_path))?;
+        let mut paths = Vec::new();
         for entry in entries {
-            let path = entry.unwrap().path();
-            if path.is_symlink() {
-                //TODO: Only remove symlinks if they match the project name in the config.json file
-                println!("🧱 Removing symlink: {:?}", path);
-               remove_file(&path).unwrap(); 
+            let path = entry
+                .map_err(GitspaceError::filesystem(&space_path))?
+                .path();
+            if path.file_name() != Some(TRASH.as_ref()) {
+                paths.push(path);
             }
         }
+        paths.sort();
+        if paths.is_empty() {
+            return Ok(CleanReport::default());
+        }
+        Ok(CleanReport::trashed(
+            self.move_to_trash(".space directory", &paths)?,
+        ))
     }
 
-    /// remove the .gitspace directory
-    pub fn rm_space(&self) {
-        let space_path = &self.get_path_as_string(&PathType::Space);
-        remove_dir_all(&space_path).unwrap();
-        println!("🧱 Removed .space directory");
-    }
-
-    /// Clone all repositories from config.json
-    pub fn clone_repos(&self, key_path: &Path) {
-        self.repositories.iter().for_each(|repo| {
-            // Instantiate buider for cloning, callbacks for processing credentials/auth request
-            println!("🧱 repo.project: {:?}", &repo.project);
-            if Path::new(&self.get_path_as_string(&PathType::Repositories)).exists() {
-                println!("👀 repo exists");
-                if !self.dir_is_empty(&PathType::Repositories, &repo.project) {
-                    let mut callbacks = RemoteCallbacks::new();
-                    callbacks.credentials(|_url, username_from_url, _allowed_types| {
-                        Cred::ssh_key(username_from_url.unwrap(), None, key_path, None)
-                    });
-                    let mut fetch_options = FetchOptions::new();
-                    let _ = &fetch_options.remote_callbacks(callbacks);
-
-                    // println!("repo.project: {}", &repo.project);
-                    let repo_uri = format!(
-                        "git@{}:{}/{}",
-                        &self.ssh.host_name, &repo.namespace, &repo.project
-                    );
-
-                    println!("👀 dir is empty");
-                    let mut repo_dir = PathBuf::new();
-                    repo_dir.push(&self.get_path_as_string(&PathType::Repositories));
-                    repo_dir.push(&repo.project);
-
-                    println!("🧱 Cloning {} into {}", &repo.project, &repo_dir.display());
-                    create_dir_all(&repo_dir).unwrap();
-                    println!(
-                        "🚀 Cloning {} into {}",
-                        &repo_uri,
-                        &repo_dir.display().to_string()
-                    );
-                    let mut builder = build::RepoBuilder::new();
-                    builder.fetch_options(fetch_options);
-                    builder.clone(&repo_uri, &repo_dir).unwrap();
+    /// Where `repo` is cloned, following `paths.layout`
+    pub fn repo_dir(&self, repo: &Repo) -> PathBuf {
+        let repositories_path = PathBuf::from(self.get_path_as_string(&PathType::Repositories));
+        match self.paths.layout {
+            StorageLayout::Flat => repositories_path.join(&repo.project),
+            StorageLayout::ByHost => repositories_path
+                .join(&self.ssh.host_name)
+                .join(&repo.namespace)
+                .join(&repo.project),
+        }
+    }
+
+    /// Fail when two repositories would be cloned into, or linked at, the same path or one inside
+    /// the other; every collision is listed, one per line
+    pub fn check_collisions(&self) -> Result<()> {
+        let collisions: Vec<String> = self.collisions().iter().map(Problem::to_string).collect();
+        match collisions.is_empty() {
+            true => Ok(()),
+            false => Err(GitspaceError::Config(collisions.join("\n"))),
+        }
+    }
+
+    /// Repositories listed twice, or cloned into or linked at the same path as an earlier one or
+    /// inside it; each is reported once, against the later repository
+    fn collisions(&self) -> Vec<Problem> {
+        let name = |repo: &Repo| format!("{}/{}", repo.namespace, repo.project);
+        // A blank project is reported on its own; its clone would be the repositories directory
+        let named = |repo: &Repo| !repo.project.trim().is_empty();
+        let mut problems: Vec<Problem> = Vec::new();
+        for (i, repo) in self
+            .repositories
+            .iter()
+            .enumerate()
+            .filter(|(_, repo)| named(repo))
+        {
+            let field = |name: &str| format!("repositories[{}].{}", i, name);
+            let others = self.repositories[..i].iter().enumerate();
+            for (j, other) in others.filter(|(_, other)| named(other)) {
+                let links = (self.link_path(other).ok(), self.link_path(repo).ok());
+                let problem = if repo.namespace == other.namespace && repo.project == other.project
+                {
+                    Problem::error(
+                        field("project"),
+                        format!("{} is already listed as repositories[{}]", name(repo), j),
+                    )
+                } else if let Some(how) = overlap(&self.repo_dir(other), &self.repo_dir(repo)) {
+                    Problem::error(
+                        field("project"),
+                        format!(
+                            "{} and {} would be cloned {}; set paths.layout to \"by-host\"",
+                            name(other),
+                            name(repo),
+                            how
+                        ),
+                    )
+                } else if let (Some(other_link), Some(link)) = links {
+                    match overlap(&other_link, &link) {
+                        Some(how) => Problem::error(
+                            field("symlink"),
+                            format!(
+                                "{} and {} would be linked {}; set symlink on one of them or symlinks.layout to \"by-namespace\"",
+                                name(other),
+                                name(repo),
+                                how
+                            ),
+                        ),
+                        None => continue,
+                    }
+                } else {
+                    continue;
+                };
+                // Colliding with a repository that's listed twice would be reported twice
+                if !problems.contains(&problem) {
+                    problems.push(problem);
                 }
-                // else {
-                //     println!("🧱 {} already exists", &repo.project);
-                //     println!("Pulling {}", &repo_uri);
-                //     let repo = Repository::open(&repo_uri).unwrap();
-                //     let mut remote = repo.find_remote("origin").unwrap();
-                //     //TODO: Handle multiple branch names, not just master
-                //     remote
-                //         .fetch(&["master"], Some(&mut fetch_options), None)
-                //         .unwrap();
-                // }
-            } else {
-                //TODO: Add the fetch case
-                println!("🧱 repositories directory does not exist. Please init first");
             }
-        });
+        }
+        problems
+    }
+
+    /// Every problem that would make `sync` fail or do something surprising, in config order with
+    /// collisions last
+    /// `ssh_key` is the `--ssh-key` override; the config's identityFile isn't checked when it's set
+    pub fn validate(&self, ssh_key: Option<&Path>) -> Vec<Problem> {
+        let mut problems = Vec::new();
+        let paths = [
+            ("paths.space", &self.paths.space),
+            ("paths.config", &self.paths.config),
+            ("paths.repositories", &self.paths.repositories),
+        ];
+        for (field, path) in paths {
+            if path.trim().is_empty() {
+                problems.push(Problem::error(field, "must not be empty"));
+            }
+        }
+        if let Err(GitspaceError::Config(message)) = self.symlink_dir() {
+            problems.push(Problem::error("paths.symlinks", message));
+        }
+
+        if self.ssh.host_name.trim().is_empty() {
+            problems.push(Problem::error("ssh.hostName", "must not be empty"));
+        }
+        if let Some(ssh_key) = ssh_key {
+            if !ssh_key.is_file() {
+                let message = format!("{} doesn't exist", ssh_key.display());
+                problems.push(Problem::error("--ssh-key", message));
+            }
+        } else if self.ssh.identity_file.trim().is_empty() {
+            problems.push(Problem::error("ssh.identityFile", "must not be empty"));
+        } else if !Path::new(&self.ssh.identity_file).is_file() {
+            problems.push(Problem::warning(
+                "ssh.identityFile",
+                format!(
+                    "{} doesn't exist; sync needs --ssh-key instead",
+                    self.ssh.identity_file
+                ),
+            ));
+        }
+
+        if let Err(message) = validate::cron(&self.sync.cron) {
+            let message = format!("{:?}: {}", self.sync.cron, message);
+            problems.push(match self.sync.enabled {
+                true => Problem::error("sync.cron", message),
+                false => Problem::warning("sync.cron", message),
+            });
+        }
+        if self.sync.jobs == Some(0) {
+            problems.push(Problem::error("sync.jobs", "must be at least 1"));
+        }
+
+        let prefix = &self.aliases.prefix;
+        if !prefix.is_empty() && !alias::is_valid_name(prefix) {
+            problems.push(Problem::error(
+                "aliases.prefix",
+                "can only contain letters, digits, _, . and -",
+            ));
+        }
+
+        if self.repositories.is_empty() {
+            problems.push(Problem::warning("repositories", "nothing to sync"));
+        }
+        for (i, repo) in self.repositories.iter().enumerate() {
+            let field = |name: &str| format!("repositories[{}].{}", i, name);
+            if repo.namespace.trim().is_empty() {
+                problems.push(Problem::error(field("namespace"), "must not be empty"));
+            }
+            if repo.project.trim().is_empty() {
+                problems.push(Problem::error(field("project"), "must not be empty"));
+            } else if repo.project.contains('/') {
+                problems.push(Problem::error(
+                    field("project"),
+                    "must not contain /; put groups in namespace",
+                ));
+            }
+            let pins = [
+                ("rev", &repo.rev),
+                ("tag", &repo.tag),
+                ("branch", &repo.branch),
+            ];
+            let set: Vec<&str> = pins
+                .iter()
+                .filter(|(_, pin)| pin.is_some())
+                .map(|(name, _)| *name)
+                .collect();
+            if let [used, .., last] = set[..] {
+                let both = if set.len() == 2 { "both" } else { "all" };
+                problems.push(Problem::warning(
+                    field(used),
+                    format!(
+                        "{} and {} are {} set; only {} is used",
+                        set[..set.len() - 1].join(", "),
+                        last,
+                        both,
+                        used
+                    ),
+                ));
+            }
+            let alias = alias_name(repo);
+            if repo.alias.is_some() && alias.trim().is_empty() {
+                problems.push(Problem::error(field("alias"), "must not be empty"));
+            } else if !alias.is_empty() && !alias::is_valid_name(alias) {
+                let message = "can only contain letters, digits, _, . and - to be used as an alias";
+                problems.push(match repo.alias {
+                    Some(_) => Problem::error(field("alias"), message),
+                    None => Problem::warning(field("project"), format!("{}; set alias", message)),
+                });
+            }
+            if let (Some(_), Err(GitspaceError::Config(message))) =
+                (&repo.symlink, repo.symlink_path(self.symlinks.layout))
+            {
+                problems.push(Problem::error(field("symlink"), message));
+            }
+        }
+        problems.extend(self.collisions());
+        problems
+    }
+
+    /// SSH URI of a configured repository
+    pub fn repo_uri(&self, repo: &Repo) -> String {
+        format!(
+            "git@{}:{}/{}",
+            &self.ssh.host_name, &repo.namespace, &repo.project
+        )
+    }
+
+    /// Number of repositories `sync` works on at once; `sync.jobs` or the number of CPUs
+    pub fn sync_jobs(&self) -> usize {
+        self.sync
+            .jobs
+            .unwrap_or_else(|| available_parallelism().map(|jobs| jobs.get()).unwrap_or(1))
+    }
+
+    /// Clone missing repositories from config.json and fast-forward the ones already cloned,
+    /// working on up to `jobs` repositories at once
+    /// When a lockfile is given, every repository is checked out at its locked commit instead
+    /// return each repository's project name alongside the outcome of syncing it
+    pub fn sync_repos(
+        &self,
+        key_path: &Path,
+        lockfile: Option<&Lockfile>,
+        jobs: usize,
+    ) -> Vec<(String, SyncStatus)> {
+        // Cloning creates the repositories directory if `clean` removed it
+        pool::run(&self.repositories, jobs, |repo| {
+            let (repo_uri, pin) = match lockfile {
+                Some(lockfile) => match lockfile.find(&repo.namespace, &repo.project) {
+                    Some(locked) => (
+                        locked.url.to_string(),
+                        Some(Pin::Rev(locked.commit.to_string())),
+                    ),
+                    None => {
+                        let message = format!("{} is missing from lock.json", &repo.project);
+                        let status = SyncStatus::Failed(GitspaceError::Config(message));
+                        return (repo.project.to_string(), status);
+                    }
+                },
+                None => (self.repo_uri(repo), repo.pin()),
+            };
+            let repo_dir = self.repo_dir(repo);
+            // An empty directory is left behind by a clone that failed halfway
+            let cloned = repo_dir
+                .read_dir()
+                .is_ok_and(|mut entries| entries.next().is_some());
+            let result = if !cloned {
+                git::clone(
+                    &repo_uri,
+                    &repo_dir,
+                    git::fetch_options(key_path),
+                    pin.as_ref(),
+                )
+            } else {
+                git::update(&repo_dir, &mut git::fetch_options(key_path), pin.as_ref())
+            };
+            let status = result.unwrap_or_else(|e| SyncStatus::Failed(e.into()));
+            (repo.project.to_string(), status)
+        })
+    }
+
+    /// Directory of every configured repository, keyed by project name
+    /// a non-empty filter keeps only repositories matching `project` or `namespace/project`
+    pub fn repo_dirs(&self, filter: &[String]) -> Vec<(String, PathBuf)> {
+        self.selected(filter)
+            .into_iter()
+            .map(|repo| (repo.project.to_string(), self.repo_dir(repo)))
+            .collect()
+    }
+
+    /// Every name `--repo` accepts, ie. each `project` and `namespace/project`, sorted
+    pub fn repo_names(&self) -> Vec<String> {
+        let mut names: Vec<String> = self
+            .repositories
+            .iter()
+            .map(|repo| repo.project.to_string())
+            .collect();
+        names.extend(
+            self.repositories
+                .iter()
+                .map(|repo| format!("{}/{}", repo.namespace, repo.project)),
+        );
+        names.sort();
+        names.dedup();
+        names
+    }
+
+    /// Inspect the branch, working tree and upstream state of every configured repository
+    /// a non-empty filter keeps only repositories matching `project` or `namespace/project`
+    pub fn status(&self, filter: &[String]) -> Vec<RepoStatus> {
+        self.selected(filter)
+            .into_iter()
+            .map(|repo| RepoStatus::inspect(&repo.namespace, &repo.project, &self.repo_dir(repo)))
+            .collect()
+    }
+
+    /// Names in `filter` that match no repository, so callers can report them
+    pub fn unknown_repos<'a>(&self, filter: &'a [String]) -> Vec<&'a str> {
+        filter
+            .iter()
+            .filter(|name| !self.repositories.iter().any(|repo| repo.matches(name)))
+            .map(String::as_str)
+            .collect()
+    }
+
+    /// Repositories matching `filter` (all of them when empty); unknown names are skipped
+    fn selected(&self, filter: &[String]) -> Vec<&Repo> {
+        self.repositories
+            .iter()
+            .filter(|repo| filter.is_empty() || filter.iter().any(|name| repo.matches(name)))
+            .collect()
+    }
+
+    /// Resolve every cloned repository to the commit it's checked out at
+    /// repositories that aren't cloned yet are left out, and returned with the reason
+    pub fn lock(&self) -> (Lockfile, Vec<(String, String)>) {
+        let mut unlocked = Vec::new();
+        let repositories = self
+            .repositories
+            .iter()
+            .filter_map(|repo| match git::head(&self.repo_dir(repo)) {
+                Ok((url, reference, commit)) => Some(LockedRepo {
+                    namespace: repo.namespace.to_string(),
+                    project: repo.project.to_string(),
+                    url,
+                    reference: reference
+                        .or_else(|| repo.tag.as_ref().map(|tag| format!("refs/tags/{}", tag))),
+                    commit: commit.to_string(),
+                }),
+                Err(e) => {
+                    unlocked.push((repo.project.to_string(), e.message().to_string()));
+                    None
+                }
+            })
+            .collect();
+        (Lockfile { repositories }, unlocked)
+    }
+}
+
+/// Name `repo`'s alias is given, before `aliases.prefix`: its `alias`, or else its project name
+fn alias_name(repo: &Repo) -> &str {
+    repo.alias.as_deref().unwrap_or(&repo.project)
+}
+
+/// How two paths overlap, eg. `at "a"` or `at "a" and inside it at "a/b"`; `None` when neither
+/// contains the other
+fn overlap(first: &Path, second: &Path) -> Option<String> {
+    if first == second {
+        Some(format!("at {:?}", first))
+    } else if second.starts_with(first) {
+        Some(format!("at {:?} and inside it at {:?}", first, second))
+    } else if first.starts_with(second) {
+        Some(format!("at {:?} and inside it at {:?}", second, first))
+    } else {
+        None
     }
 }
 
+/// Whether `path` stays inside the directory it's relative to, ie. has no root, prefix or `..`
+fn inside_workspace(path: &Path) -> bool {
+    path.components()
+        .all(|component| matches!(component, Component::Normal(_) | Component::CurDir))
+}
+
+/// Create the symlink at `dest` pointing to `target`, or export a copy of `src` for `LinkMode::Copy`
+fn materialize(src: &Path, dest: &Path, target: &Path, mode: LinkMode) -> Result<()> {
+    match mode {
+        LinkMode::Absolute | LinkMode::Relative => {
+            symlink_dir(target, dest).map_err(GitspaceError::filesystem(dest))
+        }
+        LinkMode::Copy => {
+            create_dir_all(dest).map_err(GitspaceError::filesystem(dest))?;
+            git::export(src, dest).map_err(|e| {
+                // Don't leave an empty directory behind for a repository that isn't cloned yet
+                let _ = remove_dir(dest);
+                GitspaceError::from(e)
+            })
+        }
+    }
+}
+
+/// First of `<path>.bak`, `<path>.bak.1`, `<path>.bak.2`, ... that doesn't exist yet
+fn backup_path(path: &Path) -> PathBuf {
+    let name = path.file_name().unwrap_or_default().to_string_lossy();
+    (0..)
+        .map(|attempt| match attempt {
+            0 => path.with_file_name(format!("{}.bak", name)),
+            n => path.with_file_name(format!("{}.bak.{}", name, n)),
+        })
+        .find(|backup| backup.symlink_metadata().is_err())
+        .expect("some backup name is free")
+}
+
+/// JSON Schema of the config file, for editors to validate and autocomplete against
+pub fn schema() -> String {
+    serde_json::to_string_pretty(&schemars::schema_for!(Config)).unwrap()
+}
+
 pub trait ConfigTemplate {
     //TODO: Consider replacing to_config & to_json with From & Into
     //TODO: Consider replacing to_str with Display trait
@@ -393,6 +1331,8 @@ impl ConfigTemplate for String {
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::validate::Severity;
+    use std::fs::remove_dir_all;
 
     #[test]
     #[ignore]
@@ -401,8 +1341,8 @@ mod tests {
         if Path::new(".gitspace").exists() {
             remove_dir_all(".gitspace").unwrap();
         }
-        let config = Config::new();
-        let _ = &config.write_config();
+        let config = Config::new().unwrap();
+        config.write_config().unwrap();
         // exists checks the path stored on Config; ie. ".gitspace"
         let config_exists = config.exists(&PathType::Config);
         assert!(config_exists);
@@ -425,6 +1365,8 @@ mod tests {
                 space: String::from(GITSPACE),
                 config: String::from(CONFIG),
                 repositories: String::from(REPOS),
+                symlinks: None,
+                layout: StorageLayout::Flat,
             },
             ssh: Ssh {
                 host: "github".to_string(),
@@ -438,23 +1380,231 @@ mod tests {
                     project: "cli-gitspace".to_string(),
                     // alias: "gsp".to_string(),
                     // symlink: "cli-gitspace".to_string(),
+                    ..Default::default()
                 },
                 Repo {
                     namespace: "capswan".to_string(),
                     project: "cli-ftr".to_string(),
                     // alias: "ftr".to_string(),
                     // symlink: "cli-ftr".to_string(),
+                    ..Default::default()
                 },
             ],
             sync: Sync {
                 enabled: true,
                 cron: "30 0 * * *".to_string(),
+                jobs: None,
             },
+            symlinks: Symlinks::default(),
+            aliases: Aliases::default(),
+            root: PathBuf::new(),
         };
 
         let config_default_json = config_default.to_json();
         assert_eq!(config_default_json, config_raw.to_json());
     }
+    #[test]
+    fn symlink_paths_follow_the_layout() {
+        let mut repo = Repo {
+            namespace: "capswan".to_string(),
+            project: "billing".to_string(),
+            ..Default::default()
+        };
+        assert_eq!(
+            repo.symlink_path(SymlinkLayout::Flat).unwrap(),
+            PathBuf::from("billing")
+        );
+        assert_eq!(
+            repo.symlink_path(SymlinkLayout::ByNamespace).unwrap(),
+            PathBuf::from("capswan/billing")
+        );
+
+        repo.symlink = Some("services/billing".to_string());
+        assert_eq!(
+            repo.symlink_path(SymlinkLayout::ByNamespace).unwrap(),
+            PathBuf::from("services/billing")
+        );
+        for escaping in ["../billing", "/srv/billing", ""] {
+            repo.symlink = Some(escaping.to_string());
+            assert!(repo.symlink_path(SymlinkLayout::Flat).is_err());
+        }
+    }
+
+    #[test]
+    fn shared_project_names_need_the_by_host_layout() {
+        let mut config = Config {
+            repositories: vec![
+                Repo {
+                    namespace: "capswan".to_string(),
+                    project: "api".to_string(),
+                    ..Default::default()
+                },
+                Repo {
+                    namespace: "partner".to_string(),
+                    project: "api".to_string(),
+                    symlink: Some("partner-api".to_string()),
+                    ..Default::default()
+                },
+            ],
+            ..Default::default()
+        };
+        assert!(matches!(
+            config.check_collisions(),
+            Err(GitspaceError::Config(message)) if message.contains("by-host")
+        ));
+
+        config.paths.layout = StorageLayout::ByHost;
+        config.check_collisions().unwrap();
+        assert_eq!(
+            config.repo_dir(&config.repositories[1]),
+            Path::new(GITSPACE)
+                .join(REPOS)
+                .join("github.com/partner/api")
+        );
+
+        config.repositories[1].symlink = None;
+        assert!(matches!(
+            config.check_collisions(),
+            Err(GitspaceError::Config(message)) if message.contains("linked")
+        ));
+    }
+
+    #[test]
+    fn every_collision_is_reported_including_nested_links() {
+        let repo = |project: &str, symlink: &str| Repo {
+            namespace: "capswan".to_string(),
+            project: project.to_string(),
+            symlink: Some(symlink.to_string()),
+            ..Default::default()
+        };
+        let config = Config {
+            repositories: vec![
+                repo("api", "services"),
+                repo("web", "services/web"),
+                repo("ftr", "tools"),
+                repo("cli", "tools"),
+            ],
+            ..Default::default()
+        };
+        let Err(GitspaceError::Config(message)) = config.check_collisions() else {
+            panic!("expected collisions");
+        };
+        let lines: Vec<&str> = message.lines().collect();
+        assert_eq!(lines.len(), 2, "{}", message);
+        assert!(lines[0].contains(r#"at "services" and inside it at "services/web""#));
+        assert!(lines[1].contains(r#"capswan/ftr and capswan/cli would be linked at "tools""#));
+    }
+
+    #[test]
+    fn validation_reports_every_problem_at_once() {
+        let mut config = Config::default();
+        config.ssh.identity_file = String::from("/nonexistent/id_rsa");
+        config.sync.cron = String::from("30 25 * * *");
+        config.repositories[0].project = String::new();
+        config.repositories[1].tag = Some("v1".to_string());
+        config.repositories[1].branch = Some("main".to_string());
+        config.repositories.push(config.repositories[1].clone());
+
+        let problems: Vec<(Severity, String)> = config
+            .validate(None)
+            .into_iter()
+            .map(|problem| (problem.severity, problem.field))
+            .collect();
+        assert_eq!(
+            problems,
+            vec![
+                (Severity::Warning, "ssh.identityFile".to_string()),
+                (Severity::Error, "sync.cron".to_string()),
+                (Severity::Error, "repositories[0].project".to_string()),
+                (Severity::Warning, "repositories[1].tag".to_string()),
+                (Severity::Warning, "repositories[2].tag".to_string()),
+                (Severity::Error, "repositories[2].project".to_string()),
+            ]
+        );
+
+        // --ssh-key replaces the identityFile check, and a repository colliding with both copies
+        // of a duplicate is reported once
+        let key = tempfile::NamedTempFile::new().unwrap();
+        config.repositories.push(Repo {
+            namespace: "partner".to_string(),
+            project: "cli-ftr".to_string(),
+            symlink: Some("partner-ftr".to_string()),
+            ..Default::default()
+        });
+        let problems = config.validate(Some(key.path()));
+        assert!(!problems
+            .iter()
+            .any(|problem| problem.field == "ssh.identityFile"));
+        let collisions: Vec<&Problem> = problems
+            .iter()
+            .filter(|problem| problem.field == "repositories[3].project")
+            .collect();
+        assert_eq!(collisions.len(), 1, "{:?}", collisions);
+        assert!(collisions[0]
+            .message
+            .contains("capswan/cli-ftr and partner/cli-ftr"));
+
+        let schema: Value = serde_json::from_str(&schema()).unwrap();
+        assert_eq!(schema["additionalProperties"], Value::Bool(false));
+        assert!(schema["$defs"]["Repo"]["properties"]["symlink"].is_object());
+    }
+
+    #[test]
+    fn gitignore_covers_clones_and_links() {
+        let mut config = Config::default();
+        config.paths.symlinks = Some("src".to_string());
+        config.repositories[1].symlink = Some("tools/ftr".to_string());
+        assert_eq!(
+            config.gitignore_entries().unwrap(),
+            vec![
+                "/.space/repositories/",
+                "/.space/trash/",
+                "/src/cli-gitspace",
+                "/src/tools/ftr"
+            ]
+        );
+    }
+
+    #[test]
+    fn repo_filters_skip_and_return_unknown_names() {
+        let config = Config::default();
+        let filter = vec!["capswan/cli-ftr".to_string(), "typo".to_string()];
+        let selected: Vec<String> = config
+            .repo_dirs(&filter)
+            .into_iter()
+            .map(|(project, _)| project)
+            .collect();
+        assert_eq!(selected, ["cli-ftr"]);
+        assert_eq!(config.unknown_repos(&filter), ["typo"]);
+    }
+
+    #[test]
+    fn aliases_default_to_the_project_name() {
+        let mut config = Config::default().with_root("/work");
+        config.aliases.prefix = "gs-".to_string();
+        config.repositories[0].alias = Some("gsp".to_string());
+        assert_eq!(
+            config.aliases().unwrap(),
+            vec![
+                (
+                    "gs-gsp".to_string(),
+                    PathBuf::from("/work/.space/repositories/cli-gitspace")
+                ),
+                (
+                    "gs-cli-ftr".to_string(),
+                    PathBuf::from("/work/.space/repositories/cli-ftr")
+                ),
+            ]
+        );
+
+        config.repositories[0].alias = Some("gsp; rm -rf ~".to_string());
+        assert!(matches!(config.aliases(), Err(GitspaceError::Config(_))));
+        assert!(config.validate(None).contains(&Problem::error(
+            "repositories[0].alias",
+            "can only contain letters, digits, _, . and - to be used as an alias"
+        )));
+    }
+
     #[test]
     fn get_space_path() {
         let config = Config::default();
@@ -0,0 +1,192 @@
+//! Errors surfaced by gitspace commands, each mapped to its own exit code
+use git2::{ErrorClass, ErrorCode};
+use std::fmt::{self, Display};
+use std::io;
+use std::path::PathBuf;
+
+use crate::format::{FormatError, ParseError};
+use crate::validate::{self, Problem};
+
+pub type Result<T> = std::result::Result<T, GitspaceError>;
+
+#[derive(Debug)]
+pub enum GitspaceError {
+    /// No .space/config.{json,yaml,yml,toml} in the given directory or any of its parents
+    NotAWorkspace(PathBuf),
+    /// config.json or lock.json couldn't be read or written
+    ConfigIo { path: PathBuf, source: io::Error },
+    /// config.json or lock.json doesn't parse, or doesn't fit its schema
+    ConfigParse { path: PathBuf, source: FormatError },
+    /// Config parsed but doesn't make sense, eg. a repository missing from lock.json
+    Config(String),
+    /// Validation found errors; every problem is listed, warnings included
+    Invalid(Vec<Problem>),
+    /// The remote rejected the ssh key, or the key couldn't be loaded
+    Auth(git2::Error),
+    /// The remote couldn't be reached
+    Network(git2::Error),
+    /// Any other git failure
+    Git(git2::Error),
+    /// Creating or removing repositories, symlinks or the .space directory failed
+    Filesystem { path: PathBuf, source: io::Error },
+    /// `clean` refused to delete repositories; each project is listed with what would be lost
+    UnsavedWork(Vec<(String, Vec<String>)>),
+}
+
+impl GitspaceError {
+    /// Process exit code for this category of error; 1 is left for partial failures
+    pub fn exit_code(&self) -> i32 {
+        match self {
+            GitspaceError::NotAWorkspace(_) | GitspaceError::ConfigIo { .. } => 2,
+            GitspaceError::ConfigParse { .. }
+            | GitspaceError::Config(_)
+            | GitspaceError::Invalid(_) => 3,
+            GitspaceError::Auth(_) => 4,
+            GitspaceError::Network(_) => 5,
+            GitspaceError::Git(_) => 6,
+            GitspaceError::Filesystem { .. } => 7,
+            GitspaceError::UnsavedWork(_) => 8,
+        }
+    }
+
+    pub fn config_io(path: impl Into<PathBuf>) -> impl FnOnce(io::Error) -> GitspaceError {
+        |source| GitspaceError::ConfigIo {
+            path: path.into(),
+            source,
+        }
+    }
+
+    pub fn config_parse<E: Into<FormatError>>(
+        path: impl Into<PathBuf>,
+    ) -> impl FnOnce(E) -> GitspaceError {
+        |source| GitspaceError::ConfigParse {
+            path: path.into(),
+            source: source.into(),
+        }
+    }
+
+    pub fn filesystem(path: impl Into<PathBuf>) -> impl FnOnce(io::Error) -> GitspaceError {
+        |source| GitspaceError::Filesystem {
+            path: path.into(),
+            source,
+        }
+    }
+}
+
+impl Display for GitspaceError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            GitspaceError::NotAWorkspace(path) => write!(
+                f,
+                "not a gitspace workspace: no .space/config.{{json,yaml,yml,toml}} in {} or its parents",
+                path.display()
+            ),
+            GitspaceError::ConfigIo { path, source } => {
+                write!(f, "could not access {}: {}", path.display(), source)
+            }
+            GitspaceError::ConfigParse { path, source } => {
+                match source.downcast_ref::<ParseError>() {
+                    Some(ParseError {
+                        location: Some((line, column)),
+                        ..
+                    }) => write!(
+                        f,
+                        "could not parse {}:{}:{}: {}",
+                        path.display(),
+                        line,
+                        column,
+                        source
+                    ),
+                    _ => write!(f, "could not parse {}: {}", path.display(), source),
+                }
+            }
+            GitspaceError::Config(message) => write!(f, "invalid config: {}", message),
+            GitspaceError::Invalid(problems) => {
+                write!(f, "invalid config:\n{}", validate::render(problems))
+            }
+            GitspaceError::Auth(e) => write!(
+                f,
+                "authentication failed: {} (check ssh.identityFile or --ssh-key)",
+                e.message()
+            ),
+            GitspaceError::Network(e) => write!(f, "network error: {}", e.message()),
+            GitspaceError::Git(e) => write!(f, "git error: {}", e.message()),
+            GitspaceError::Filesystem { path, source } => {
+                write!(f, "{}: {}", path.display(), source)
+            }
+            GitspaceError::UnsavedWork(repositories) => {
+                write!(
+                    f,
+                    "refusing to remove repositories with unsaved work (use --force to remove anyway):"
+                )?;
+                repositories.iter().try_for_each(|(project, reasons)| {
+                    write!(f, "\n   {}: {}", project, reasons.join(", "))
+                })
+            }
+        }
+    }
+}
+
+impl std::error::Error for GitspaceError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            GitspaceError::ConfigIo { source, .. } | GitspaceError::Filesystem { source, .. } => {
+                Some(source)
+            }
+            GitspaceError::ConfigParse { source, .. } => Some(source.as_ref()),
+            GitspaceError::Auth(e) | GitspaceError::Network(e) | GitspaceError::Git(e) => Some(e),
+            GitspaceError::NotAWorkspace(_)
+            | GitspaceError::Config(_)
+            | GitspaceError::Invalid(_)
+            | GitspaceError::UnsavedWork(_) => None,
+        }
+    }
+}
+
+impl From<git2::Error> for GitspaceError {
+    fn from(e: git2::Error) -> Self {
+        match (e.code(), e.class()) {
+            (ErrorCode::Auth, _) | (_, ErrorClass::Ssh) => GitspaceError::Auth(e),
+            (ErrorCode::Certificate, _)
+            | (_, ErrorClass::Net)
+            | (_, ErrorClass::Http)
+            | (_, ErrorClass::Ssl) => GitspaceError::Network(e),
+            _ => GitspaceError::Git(e),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn git_errors_are_categorised() {
+        let auth = git2::Error::new(ErrorCode::Auth, ErrorClass::Ssh, "no key");
+        let network = git2::Error::new(ErrorCode::GenericError, ErrorClass::Net, "timeout");
+        let git = git2::Error::from_str("revspec not found");
+
+        assert!(matches!(GitspaceError::from(auth), GitspaceError::Auth(_)));
+        assert!(matches!(
+            GitspaceError::from(network),
+            GitspaceError::Network(_)
+        ));
+        assert!(matches!(GitspaceError::from(git), GitspaceError::Git(_)));
+    }
+
+    #[test]
+    fn categories_have_distinct_exit_codes() {
+        let missing = || io::Error::from(io::ErrorKind::NotFound);
+        let errors = [
+            GitspaceError::config_io(".space/config.json")(missing()),
+            GitspaceError::Config("missing from lock.json".to_string()),
+            GitspaceError::Auth(git2::Error::from_str("auth")),
+            GitspaceError::Network(git2::Error::from_str("net")),
+            GitspaceError::Git(git2::Error::from_str("git")),
+            GitspaceError::filesystem(".space/repositories")(missing()),
+            GitspaceError::UnsavedWork(vec![]),
+        ];
+        let codes: Vec<i32> = errors.iter().map(GitspaceError::exit_code).collect();
+        assert_eq!(codes, vec![2, 3, 4, 5, 6, 7, 8]);
+    }
+}
@@ -0,0 +1,246 @@
+//! Run a shell command in every cloned repository
+use std::fmt::{self, Display};
+use std::io::{BufRead, BufReader, Read};
+use std::path::{Path, PathBuf};
+use std::process::{Command, Stdio};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::thread;
+
+use crate::pool;
+
+/// Outcome of running the command in a single repository
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub enum ExecStatus {
+    Succeeded,
+    /// Exit code, or `None` when the process was killed by a signal
+    Failed(Option<i32>),
+    /// Never started, eg. not cloned or stopped by `--fail-fast`
+    Skipped(String),
+}
+
+impl Display for ExecStatus {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ExecStatus::Succeeded => write!(f, "ok"),
+            ExecStatus::Failed(Some(code)) => write!(f, "exit {}", code),
+            ExecStatus::Failed(None) => write!(f, "terminated by signal"),
+            ExecStatus::Skipped(reason) => write!(f, "skipped ({})", reason),
+        }
+    }
+}
+
+pub struct ExecOptions {
+    /// Number of repositories to run the command in at once
+    pub parallel: usize,
+    /// Stop starting new repositories after the first failure
+    pub fail_fast: bool,
+}
+
+/// Run `command` through the platform shell in each `(project, repo_dir)` target
+/// return each project's outcome in the same order as `targets`
+pub fn exec(
+    targets: &[(String, PathBuf)],
+    command: &str,
+    options: &ExecOptions,
+) -> Vec<(String, ExecStatus)> {
+    let failed = AtomicBool::new(false);
+    let width = targets
+        .iter()
+        .map(|(project, _)| project.len())
+        .max()
+        .unwrap_or_default();
+
+    let statuses = pool::run(targets, options.parallel, |(project, repo_dir)| {
+        let status = if options.fail_fast && failed.load(Ordering::SeqCst) {
+            ExecStatus::Skipped("fail-fast".to_string())
+        } else if !repo_dir.exists() {
+            ExecStatus::Skipped("not cloned".to_string())
+        } else {
+            run(
+                &format!("{:width$}", project, width = width),
+                repo_dir,
+                command,
+            )
+        };
+        if let ExecStatus::Failed(_) = status {
+            failed.store(true, Ordering::SeqCst);
+        }
+        status
+    });
+
+    targets
+        .iter()
+        .map(|(project, _)| project.to_string())
+        .zip(statuses)
+        .collect()
+}
+
+/// Run the command in `repo_dir`, streaming every output line prefixed with the project name
+fn run(prefix: &str, repo_dir: &Path, command: &str) -> ExecStatus {
+    let mut child = match shell(command)
+        .current_dir(repo_dir)
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+    {
+        Ok(child) => child,
+        Err(e) => {
+            eprintln!("{} | {}", prefix, e);
+            return ExecStatus::Failed(None);
+        }
+    };
+    let stdout = child.stdout.take().unwrap();
+    let stderr = child.stderr.take().unwrap();
+    thread::scope(|scope| {
+        scope.spawn(|| stream(stdout, |line| println!("{} | {}", prefix, line)));
+        stream(stderr, |line| eprintln!("{} | {}", prefix, line));
+    });
+    match child.wait() {
+        Ok(exit) if exit.success() => ExecStatus::Succeeded,
+        Ok(exit) => ExecStatus::Failed(exit.code()),
+        Err(_) => ExecStatus::Failed(None),
+    }
+}
+
+fn stream(output: impl Read, print: impl Fn(&str)) {
+    BufReader::new(output)
+        .lines()
+        .map_while(Result::ok)
+        .for_each(|line| print(&line));
+}
+
+#[cfg(windows)]
+fn shell(command: &str) -> Command {
+    let mut shell = Command::new("cmd");
+    shell.args(["/C", command]);
+    shell
+}
+
+#[cfg(not(windows))]
+fn shell(command: &str) -> Command {
+    let mut shell = Command::new("sh");
+    shell.args(["-c", command]);
+    shell
+}
+
+/// Shell command line for `gitspace exec -- <argv>`
+/// a single argument is a shell snippet as written (eg. `"git log | head"`); several are quoted
+/// one by one so `git commit -m "two words"` keeps its argument together
+pub fn command_line(argv: &[String]) -> String {
+    match argv {
+        [snippet] => snippet.to_string(),
+        _ => argv
+            .iter()
+            .map(|arg| quote(arg))
+            .collect::<Vec<_>>()
+            .join(" "),
+    }
+}
+
+#[cfg(windows)]
+fn quote(arg: &str) -> String {
+    if !arg.is_empty() && !arg.contains([' ', '\t', '"']) {
+        return arg.to_string();
+    }
+    format!("\"{}\"", arg.replace('"', "\\\""))
+}
+
+#[cfg(not(windows))]
+fn quote(arg: &str) -> String {
+    let safe = |c: char| c.is_ascii_alphanumeric() || "-_./=:,+@%".contains(c);
+    if !arg.is_empty() && arg.chars().all(safe) {
+        return arg.to_string();
+    }
+    format!("'{}'", arg.replace('\'', r#"'\''"#))
+}
+
+/// Aggregated one-line-per-failure summary printed after every repository ran
+pub fn render_summary(results: &[(String, ExecStatus)]) -> String {
+    let count = |matches: fn(&ExecStatus) -> bool| {
+        results.iter().filter(|(_, status)| matches(status)).count()
+    };
+    let mut summary = vec![format!(
+        "🧱 {} succeeded, {} failed, {} skipped",
+        count(|status| *status == ExecStatus::Succeeded),
+        count(|status| matches!(status, ExecStatus::Failed(_))),
+        count(|status| matches!(status, ExecStatus::Skipped(_))),
+    )];
+    results
+        .iter()
+        .filter(|(_, status)| *status != ExecStatus::Succeeded)
+        .for_each(|(project, status)| summary.push(format!("   {}: {}", project, status)));
+    summary.join("\n")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::TempDir;
+
+    fn targets(dir: &TempDir, projects: &[&str]) -> Vec<(String, PathBuf)> {
+        projects
+            .iter()
+            .map(|project| {
+                let repo_dir = dir.path().join(project);
+                std::fs::create_dir_all(&repo_dir).unwrap();
+                (project.to_string(), repo_dir)
+            })
+            .collect()
+    }
+
+    #[test]
+    #[cfg(unix)]
+    fn exec_reports_each_repository() {
+        let dir = TempDir::new().unwrap();
+        let mut targets = targets(&dir, &["api", "web"]);
+        targets.push(("missing".to_string(), dir.path().join("missing")));
+        std::fs::write(dir.path().join("web/fail"), "").unwrap();
+
+        let options = ExecOptions {
+            parallel: 2,
+            fail_fast: false,
+        };
+        let results = exec(&targets, "test ! -e fail || exit 3", &options);
+        assert_eq!(results[0], ("api".to_string(), ExecStatus::Succeeded));
+        assert_eq!(results[1], ("web".to_string(), ExecStatus::Failed(Some(3))));
+        assert!(matches!(results[2].1, ExecStatus::Skipped(_)));
+        assert!(render_summary(&results).starts_with("🧱 1 succeeded, 1 failed, 1 skipped"));
+    }
+
+    #[test]
+    #[cfg(unix)]
+    fn arguments_with_spaces_stay_together() {
+        let dir = TempDir::new().unwrap();
+        let targets = targets(&dir, &["api"]);
+        let argv: Vec<String> = ["test", "two words", "=", "two words"]
+            .iter()
+            .map(|arg| arg.to_string())
+            .collect();
+        assert_eq!(command_line(&argv), "test 'two words' = 'two words'");
+        assert_eq!(
+            command_line(&["git log | head".to_string()]),
+            "git log | head"
+        );
+
+        let options = ExecOptions {
+            parallel: 1,
+            fail_fast: false,
+        };
+        let results = exec(&targets, &command_line(&argv), &options);
+        assert_eq!(results[0].1, ExecStatus::Succeeded);
+    }
+
+    #[test]
+    fn fail_fast_skips_remaining_repositories() {
+        let dir = TempDir::new().unwrap();
+        let targets = targets(&dir, &["api", "web", "docs"]);
+        let options = ExecOptions {
+            parallel: 1,
+            fail_fast: true,
+        };
+        let results = exec(&targets, "exit 1", &options);
+        assert_eq!(results[0].1, ExecStatus::Failed(Some(1)));
+        assert_eq!(results[1].1, ExecStatus::Skipped("fail-fast".to_string()));
+        assert_eq!(results[2].1, ExecStatus::Skipped("fail-fast".to_string()));
+    }
+}
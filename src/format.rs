@@ -0,0 +1,225 @@
+//! Languages the workspace config can be written in, picked by the config file's extension
+use serde::de::DeserializeOwned;
+use serde::Serialize;
+use std::error::Error;
+use std::fmt::{self, Display};
+use std::path::Path;
+use std::str::FromStr;
+
+/// Errors from any of the config languages
+pub type FormatError = Box<dyn Error + Send + Sync>;
+
+#[derive(Debug, PartialEq, Eq, Clone, Copy, Default)]
+pub enum ConfigFormat {
+    #[default]
+    Json,
+    Yaml,
+    Toml,
+}
+
+/// Config file names looked up in .space, in order of preference
+pub const CONFIG_FILES: [&str; 4] = ["config.json", "config.yaml", "config.yml", "config.toml"];
+
+impl ConfigFormat {
+    /// Format of the config at `path`, by extension; anything unrecognised is read as JSON
+    pub fn from_path(path: &Path) -> ConfigFormat {
+        match path.extension().and_then(|extension| extension.to_str()) {
+            Some("yaml" | "yml") => ConfigFormat::Yaml,
+            Some("toml") => ConfigFormat::Toml,
+            _ => ConfigFormat::Json,
+        }
+    }
+
+    /// Name of a config file in this format, eg. `config.yaml`
+    pub fn file_name(&self) -> String {
+        format!("config.{}", self)
+    }
+
+    /// JSON is read as JSON5, so comments and trailing commas are fine
+    /// errors point at the line and column the parser stopped at when it knows them
+    pub fn parse<T: DeserializeOwned>(&self, contents: &str) -> Result<T, ParseError> {
+        match self {
+            ConfigFormat::Json => json5::from_str(contents).map_err(|e| {
+                let json5::Error::Message { msg, location } = e;
+                // Syntax errors come with their own snippet; only keep what was expected
+                let message = match msg.lines().last() {
+                    Some(expected) if msg.trim_start().starts_with("-->") => {
+                        expected.trim_start_matches([' ', '=']).to_string()
+                    }
+                    _ => msg,
+                };
+                let location = location.map(|location| (location.line, location.column));
+                ParseError::new(message, location, contents)
+            }),
+            ConfigFormat::Yaml => serde_yaml::from_str(contents).map_err(|e| {
+                let message = e.to_string();
+                let message = match message.rfind(" at line ") {
+                    Some(end) => message[..end].to_string(),
+                    None => message,
+                };
+                let location = e
+                    .location()
+                    .map(|location| (location.line(), location.column()));
+                ParseError::new(message, location, contents)
+            }),
+            ConfigFormat::Toml => toml::from_str(contents).map_err(|e| {
+                let location = e.span().map(|span| line_column(contents, span.start));
+                ParseError::new(e.message(), location, contents)
+            }),
+        }
+    }
+
+    pub fn render<T: Serialize>(&self, value: &T) -> Result<String, FormatError> {
+        match self {
+            ConfigFormat::Json => Ok(serde_json::to_string_pretty(value)?),
+            ConfigFormat::Yaml => Ok(serde_yaml::to_string(value)?),
+            ConfigFormat::Toml => Ok(toml::to_string_pretty(value)?),
+        }
+    }
+}
+
+/// A config that didn't parse or didn't fit the schema, eg. unknown field `repositorys`
+#[derive(Debug, PartialEq, Eq)]
+pub struct ParseError {
+    pub message: String,
+    /// One-based line and column
+    pub location: Option<(usize, usize)>,
+    /// The offending line with a caret under the column
+    pub snippet: Option<String>,
+}
+
+impl ParseError {
+    fn new(message: impl Into<String>, location: Option<(usize, usize)>, contents: &str) -> Self {
+        let snippet = location.and_then(|(line, column)| {
+            let source = contents.lines().nth(line.checked_sub(1)?)?;
+            let gutter = " ".repeat(line.to_string().len());
+            let caret = " ".repeat(column.saturating_sub(1));
+            Some(format!(
+                "{} |\n{} | {}\n{} | {}^",
+                gutter, line, source, gutter, caret
+            ))
+        });
+        ParseError {
+            message: message.into(),
+            location,
+            snippet,
+        }
+    }
+}
+
+impl Display for ParseError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.message)?;
+        match &self.snippet {
+            Some(snippet) => write!(f, "\n{}", snippet),
+            None => Ok(()),
+        }
+    }
+}
+
+impl Error for ParseError {}
+
+/// One-based line and column of the byte `offset` in `contents`
+fn line_column(contents: &str, offset: usize) -> (usize, usize) {
+    let before = &contents[..offset.min(contents.len())];
+    let line = before.matches('\n').count() + 1;
+    let column = before
+        .rsplit('\n')
+        .next()
+        .unwrap_or_default()
+        .chars()
+        .count()
+        + 1;
+    (line, column)
+}
+
+impl Display for ConfigFormat {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ConfigFormat::Json => write!(f, "json"),
+            ConfigFormat::Yaml => write!(f, "yaml"),
+            ConfigFormat::Toml => write!(f, "toml"),
+        }
+    }
+}
+
+impl FromStr for ConfigFormat {
+    type Err = String;
+
+    fn from_str(format: &str) -> Result<Self, Self::Err> {
+        match format {
+            "json" => Ok(ConfigFormat::Json),
+            "yaml" | "yml" => Ok(ConfigFormat::Yaml),
+            "toml" => Ok(ConfigFormat::Toml),
+            _ => Err(format!(
+                "unsupported config format {} (use json, yaml or toml)",
+                format
+            )),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::{Config, ConfigTemplate};
+
+    #[test]
+    fn every_format_round_trips_the_default_config() {
+        let config = Config::default();
+        for format in [ConfigFormat::Json, ConfigFormat::Yaml, ConfigFormat::Toml] {
+            let rendered = format.render(&config).unwrap();
+            let parsed: Config = format.parse(&rendered).unwrap();
+            assert_eq!(parsed, config, "{}", format);
+            assert_eq!(
+                ConfigFormat::from_path(Path::new(&format.file_name())),
+                format
+            );
+        }
+        assert_eq!(
+            ConfigFormat::from_path(Path::new(".space/config.yml")),
+            ConfigFormat::Yaml
+        );
+    }
+
+    #[test]
+    fn json_allows_comments_and_points_at_mistakes() {
+        let config = Config::default().to_str();
+        let commented = config.replacen("{", "{\n  // workspace\n", 1).replacen(
+            "\"cron\": \"30 0 * * *\"",
+            "\"cron\": \"30 0 * * *\", /* daily */",
+            1,
+        );
+        let parsed: Config = ConfigFormat::Json.parse(&commented).unwrap();
+        assert_eq!(parsed, Config::default());
+
+        let typo = commented.replacen("\"repositories\": [", "\"repositorys\": [", 1);
+        let error = ConfigFormat::Json.parse::<Config>(&typo).unwrap_err();
+        assert!(error.message.starts_with("unknown field `repositorys`"));
+        let (line, column) = error.location.unwrap();
+        assert_eq!(
+            typo.lines().nth(line - 1).unwrap().trim(),
+            "\"repositorys\": ["
+        );
+        assert_eq!(column, 3);
+        assert!(error.snippet.unwrap().ends_with("|   ^"));
+
+        let missing = commented.replacen("\"identityFile\"", "// \"identityFile\"", 1);
+        let error = ConfigFormat::Json.parse::<Config>(&missing).unwrap_err();
+        assert!(error.message.contains("missing field `identityFile`"));
+        let (line, column) = error.location.unwrap();
+        let ssh = missing
+            .lines()
+            .position(|line| line.contains("\"ssh\""))
+            .unwrap();
+        assert_eq!(line, ssh + 1);
+        assert_eq!(
+            column,
+            missing.lines().nth(ssh).unwrap().find('{').unwrap() + 1
+        );
+
+        let toml = "[paths]\nspace = \".space\"\nconfig = 1\n";
+        let error = ConfigFormat::Toml.parse::<Config>(toml).unwrap_err();
+        assert_eq!(error.location, Some((3, 10)));
+    }
+}
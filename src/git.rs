@@ -0,0 +1,565 @@
+//! Git operations used by `sync`
+use git2::build::{CheckoutBuilder, RepoBuilder};
+use git2::{
+    AutotagOption, BranchType, Cred, ErrorCode, FetchOptions, Index, ObjectType, Oid,
+    RemoteCallbacks, Repository, StatusOptions, TreeWalkMode, TreeWalkResult,
+};
+use std::fmt::{self, Display};
+use std::fs::{read, read_dir};
+use std::io;
+use std::path::Path;
+
+use crate::error::GitspaceError;
+
+/// A ref a repository is pinned to in config.json
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub enum Pin {
+    Branch(String),
+    Tag(String),
+    Rev(String),
+}
+
+impl Display for Pin {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Pin::Branch(branch) => write!(f, "branch {}", branch),
+            Pin::Tag(tag) => write!(f, "tag {}", tag),
+            Pin::Rev(rev) => write!(f, "rev {}", rev),
+        }
+    }
+}
+
+/// Outcome of syncing a single repository
+#[derive(Debug)]
+pub enum SyncStatus {
+    /// Freshly cloned into the repositories directory
+    Cloned,
+    /// Checked-out branch was fast-forwarded, or HEAD was moved to the pinned ref
+    Updated,
+    /// HEAD already matches its upstream or pinned ref
+    UpToDate,
+    /// HEAD is not on a branch, so there is nothing to fast-forward
+    Detached,
+    /// Local branch and upstream both have commits the other doesn't
+    Diverged,
+    /// Tracked files have uncommitted changes; left untouched
+    Dirty,
+    /// Kept per repository so the remaining repos still sync
+    Failed(GitspaceError),
+}
+
+impl Display for SyncStatus {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            SyncStatus::Cloned => write!(f, "cloned"),
+            SyncStatus::Updated => write!(f, "updated"),
+            SyncStatus::UpToDate => write!(f, "up to date"),
+            SyncStatus::Detached => write!(f, "fetched (detached HEAD, not updated)"),
+            SyncStatus::Diverged => write!(f, "diverged from upstream, not updated"),
+            SyncStatus::Dirty => write!(f, "has local changes, not updated"),
+            SyncStatus::Failed(e) => write!(f, "failed: {}", e),
+        }
+    }
+}
+
+/// Fetch options that authenticate against the remote with the given ssh key
+pub fn fetch_options(key_path: &Path) -> FetchOptions<'_> {
+    let mut callbacks = RemoteCallbacks::new();
+    callbacks.credentials(move |_url, username_from_url, _allowed_types| {
+        Cred::ssh_key(username_from_url.unwrap_or("git"), None, key_path, None)
+    });
+    let mut fetch_options = FetchOptions::new();
+    fetch_options.remote_callbacks(callbacks);
+    // Tags are fetched eagerly so tag pins resolve even when they aren't on a fetched branch
+    fetch_options.download_tags(AutotagOption::All);
+    fetch_options
+}
+
+/// Clone `uri` into `repo_dir` and check out the pinned ref, if any
+pub fn clone(
+    uri: &str,
+    repo_dir: &Path,
+    fetch_options: FetchOptions<'_>,
+    pin: Option<&Pin>,
+) -> Result<SyncStatus, git2::Error> {
+    let mut builder = RepoBuilder::new();
+    builder.fetch_options(fetch_options);
+    if let Some(Pin::Branch(branch)) = pin {
+        builder.branch(branch);
+    }
+    let repo = builder.clone(uri, repo_dir)?;
+    if let Some(oid) = pinned_commit(&repo, pin)? {
+        checkout_detached(&repo, oid)?;
+    }
+    Ok(SyncStatus::Cloned)
+}
+
+/// Fetch origin and move HEAD to the pinned ref, or fast-forward the checked-out branch
+///
+/// Never rewrites local work: diverged branches and dirty working trees are reported and skipped
+pub fn update(
+    repo_dir: &Path,
+    fetch_options: &mut FetchOptions<'_>,
+    pin: Option<&Pin>,
+) -> Result<SyncStatus, git2::Error> {
+    let repo = Repository::open(repo_dir)?;
+    // An empty refspec list fetches with the remote's configured refspecs
+    repo.find_remote("origin")?
+        .fetch(&[] as &[&str], Some(fetch_options), None)?;
+
+    if let Some(oid) = pinned_commit(&repo, pin)? {
+        return checkout_detached(&repo, oid);
+    }
+
+    let mut switched = false;
+    if let Some(Pin::Branch(branch)) = pin {
+        let head = repo.head()?;
+        if !head.is_branch() || head.shorthand() != Some(branch.as_str()) {
+            if is_dirty(&repo)? {
+                return Ok(SyncStatus::Dirty);
+            }
+            checkout_branch(&repo, branch)?;
+            switched = true;
+        }
+    }
+
+    match fast_forward(&repo)? {
+        SyncStatus::UpToDate if switched => Ok(SyncStatus::Updated),
+        status => Ok(status),
+    }
+}
+
+/// Fast-forward the checked-out branch to its upstream
+fn fast_forward(repo: &Repository) -> Result<SyncStatus, git2::Error> {
+    let head = repo.head()?;
+    if !head.is_branch() {
+        return Ok(SyncStatus::Detached);
+    }
+    let head_name = head.name().unwrap_or_default().to_string();
+    let branch_name = head.shorthand().unwrap_or_default().to_string();
+
+    let upstream = match repo
+        .find_branch(&branch_name, BranchType::Local)?
+        .upstream()
+    {
+        Ok(upstream) => upstream.into_reference(),
+        Err(_) => repo.find_reference(&format!("refs/remotes/origin/{}", branch_name))?,
+    };
+    let upstream_commit = repo.reference_to_annotated_commit(&upstream)?;
+    let (analysis, _) = repo.merge_analysis(&[&upstream_commit])?;
+
+    if analysis.is_up_to_date() {
+        return Ok(SyncStatus::UpToDate);
+    }
+    if !analysis.is_fast_forward() {
+        return Ok(SyncStatus::Diverged);
+    }
+    if is_dirty(repo)? {
+        return Ok(SyncStatus::Dirty);
+    }
+
+    repo.find_reference(&head_name)?
+        .set_target(upstream_commit.id(), "gitspace: fast-forward")?;
+    repo.set_head(&head_name)?;
+    repo.checkout_head(Some(CheckoutBuilder::default().force()))?;
+    Ok(SyncStatus::Updated)
+}
+
+/// Commit a tag or rev pin resolves to; branch pins and no pin resolve to `None`
+fn pinned_commit(repo: &Repository, pin: Option<&Pin>) -> Result<Option<Oid>, git2::Error> {
+    let spec = match pin {
+        Some(Pin::Tag(tag)) => format!("refs/tags/{}", tag),
+        Some(Pin::Rev(rev)) => rev.to_string(),
+        Some(Pin::Branch(_)) | None => return Ok(None),
+    };
+    let object = repo.revparse_single(&spec).map_err(|e| match e.code() {
+        ErrorCode::NotFound => git2::Error::from_str(&format!(
+            "{} is not reachable from the fetched refs",
+            pin.map(Pin::to_string).unwrap_or(spec)
+        )),
+        _ => e,
+    })?;
+    Ok(Some(object.peel_to_commit()?.id()))
+}
+
+/// Origin URL, checked-out ref name (`None` when detached) and HEAD commit of a cloned repository
+pub fn head(repo_dir: &Path) -> Result<(String, Option<String>, Oid), git2::Error> {
+    let repo = Repository::open(repo_dir)?;
+    let url = repo
+        .find_remote("origin")?
+        .url()
+        .unwrap_or_default()
+        .to_string();
+    let head = repo.head()?;
+    let reference = if head.is_branch() {
+        head.name().map(String::from)
+    } else {
+        None
+    };
+    let commit = head.peel_to_commit()?.id();
+    Ok((url, reference, commit))
+}
+
+/// Write the files of the commit HEAD points at into `dest`, without a `.git` directory
+/// the repository's own index and working tree are left untouched
+pub fn export(repo_dir: &Path, dest: &Path) -> Result<(), git2::Error> {
+    let repo = Repository::open(repo_dir)?;
+    let tree = repo.head()?.peel_to_tree()?;
+    let mut index = Index::new()?;
+    index.read_tree(&tree)?;
+    let mut checkout = CheckoutBuilder::new();
+    checkout.target_dir(dest).force();
+    repo.checkout_index(Some(&mut index), Some(&mut checkout))
+}
+
+/// Whether `dest` holds exactly what `export` would write there: the files of the commit HEAD
+/// points at, unchanged, and nothing else
+pub fn matches_export(repo_dir: &Path, dest: &Path) -> Result<bool, git2::Error> {
+    let repo = Repository::open(repo_dir)?;
+    let tree = repo.head()?.peel_to_tree()?;
+    let mut files = 0;
+    let mut matches = true;
+    let walked = tree.walk(TreeWalkMode::PreOrder, |parent, entry| {
+        if entry.kind() != Some(ObjectType::Blob) {
+            return TreeWalkResult::Ok;
+        }
+        files += 1;
+        let path = dest.join(parent).join(entry.name().unwrap_or_default());
+        // Symlinks are stored as blobs holding their target
+        let contents = match path.read_link() {
+            Ok(target) => Some(target.to_string_lossy().into_owned().into_bytes()),
+            Err(_) => read(&path).ok(),
+        };
+        let unchanged = contents.is_some_and(|contents| {
+            Oid::hash_object(ObjectType::Blob, &contents).ok() == Some(entry.id())
+        });
+        if unchanged {
+            TreeWalkResult::Ok
+        } else {
+            matches = false;
+            TreeWalkResult::Abort
+        }
+    });
+    if !matches {
+        return Ok(false);
+    }
+    walked?;
+    Ok(count_files(dest).ok() == Some(files))
+}
+
+/// Files and symlinks anywhere under `dir`, not following symlinked directories
+fn count_files(dir: &Path) -> io::Result<usize> {
+    let mut files = 0;
+    for entry in read_dir(dir)? {
+        let entry = entry?;
+        files += match entry.file_type()?.is_dir() {
+            true => count_files(&entry.path())?,
+            false => 1,
+        };
+    }
+    Ok(files)
+}
+
+/// Detach HEAD at `oid`, unless the working tree has changes that would be lost
+fn checkout_detached(repo: &Repository, oid: Oid) -> Result<SyncStatus, git2::Error> {
+    if repo.head_detached()? && repo.head()?.target() == Some(oid) {
+        return Ok(SyncStatus::UpToDate);
+    }
+    if is_dirty(repo)? {
+        return Ok(SyncStatus::Dirty);
+    }
+    let commit = repo.find_commit(oid)?;
+    repo.checkout_tree(commit.as_object(), Some(CheckoutBuilder::default().force()))?;
+    repo.set_head_detached(oid)?;
+    Ok(SyncStatus::Updated)
+}
+
+/// Check out a local branch, creating it from `origin/<branch>` when it doesn't exist yet
+fn checkout_branch(repo: &Repository, branch: &str) -> Result<(), git2::Error> {
+    let local = match repo.find_branch(branch, BranchType::Local) {
+        Ok(local) => local,
+        Err(_) => {
+            let upstream_name = format!("origin/{}", branch);
+            let upstream = repo.find_branch(&upstream_name, BranchType::Remote)?;
+            let commit = upstream.get().peel_to_commit()?;
+            let mut local = repo.branch(branch, &commit, false)?;
+            local.set_upstream(Some(&upstream_name))?;
+            local
+        }
+    };
+    let head_name = local.get().name().unwrap_or_default().to_string();
+    let commit = local.get().peel_to_commit()?;
+    repo.checkout_tree(commit.as_object(), Some(CheckoutBuilder::default().force()))?;
+    repo.set_head(&head_name)?;
+    Ok(())
+}
+
+/// Whether any tracked file differs from HEAD; untracked files don't count
+fn is_dirty(repo: &Repository) -> Result<bool, git2::Error> {
+    let mut options = StatusOptions::new();
+    options.include_untracked(false).include_ignored(false);
+    Ok(!repo.statuses(Some(&mut options))?.is_empty())
+}
+
+/// Everything in a cloned repository that would be lost by deleting it
+/// return one human-readable reason per problem; empty when the repository is safe to remove
+pub fn unsaved_work(repo_dir: &Path) -> Result<Vec<String>, git2::Error> {
+    let mut repo = Repository::open(repo_dir)?;
+    let mut reasons = Vec::new();
+
+    let mut options = StatusOptions::new();
+    options.include_untracked(true).include_ignored(false);
+    let changes = repo.statuses(Some(&mut options))?.len();
+    if changes > 0 {
+        reasons.push(format!("{} uncommitted change(s)", changes));
+    }
+
+    let mut stashes = 0;
+    repo.stash_foreach(|_, _, _| {
+        stashes += 1;
+        true
+    })?;
+    if stashes > 0 {
+        reasons.push(format!("{} stash(es)", stashes));
+    }
+
+    let remote_tips: Vec<Oid> = repo
+        .branches(Some(BranchType::Remote))?
+        .filter_map(|branch| branch.ok()?.0.get().target())
+        .collect();
+    for branch in repo.branches(Some(BranchType::Local))? {
+        let (branch, _) = branch?;
+        let name = branch.name()?.unwrap_or_default().to_string();
+        let tip = match branch.get().target() {
+            Some(tip) => tip,
+            None => continue,
+        };
+        match branch.upstream() {
+            Ok(upstream) => {
+                let upstream_tip = upstream.get().target().unwrap_or(tip);
+                let (ahead, _) = repo.graph_ahead_behind(tip, upstream_tip)?;
+                if ahead > 0 {
+                    reasons.push(format!("branch {} has {} unpushed commit(s)", name, ahead));
+                }
+            }
+            Err(_) => {
+                let pushed = remote_tips.iter().any(|remote_tip| {
+                    *remote_tip == tip
+                        || repo.graph_descendant_of(*remote_tip, tip).unwrap_or(false)
+                });
+                if !pushed {
+                    reasons.push(format!("branch {} only exists locally", name));
+                }
+            }
+        }
+    }
+
+    // Pinned tags and revisions leave HEAD detached; commits made there belong to no branch
+    if repo.head_detached()? {
+        if let Some(head) = repo.head()?.target() {
+            let branch_tips: Vec<Oid> = repo
+                .branches(None)?
+                .filter_map(|branch| branch.ok()?.0.get().target())
+                .collect();
+            let reachable = branch_tips
+                .iter()
+                .any(|tip| *tip == head || repo.graph_descendant_of(*tip, head).unwrap_or(false));
+            if !reachable {
+                reasons.push(String::from("detached HEAD has commits on no branch"));
+            }
+        }
+    }
+    Ok(reasons)
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+    use std::fs::write;
+    use tempfile::TempDir;
+
+    pub(crate) fn commit_file(repo: &Repository, name: &str, contents: &str) {
+        let workdir = repo.workdir().unwrap();
+        write(workdir.join(name), contents).unwrap();
+        let mut index = repo.index().unwrap();
+        index.add_path(Path::new(name)).unwrap();
+        index.write().unwrap();
+        let tree = repo.find_tree(index.write_tree().unwrap()).unwrap();
+        let signature = git2::Signature::now("gitspace", "gitspace@example.com").unwrap();
+        let parent = repo.head().ok().map(|head| head.peel_to_commit().unwrap());
+        let parents: Vec<&git2::Commit> = parent.iter().collect();
+        repo.commit(Some("HEAD"), &signature, &signature, name, &tree, &parents)
+            .unwrap();
+    }
+
+    /// An upstream repo with one commit and a local clone of it
+    pub(crate) fn upstream_and_clone() -> (TempDir, Repository, Repository) {
+        let dir = TempDir::new().unwrap();
+        let upstream = Repository::init(dir.path().join("upstream")).unwrap();
+        commit_file(&upstream, "README.md", "one");
+        let local = Repository::clone(
+            dir.path().join("upstream").to_str().unwrap(),
+            dir.path().join("local"),
+        )
+        .unwrap();
+        (dir, upstream, local)
+    }
+
+    #[test]
+    fn update_fast_forwards_then_is_idempotent() {
+        let (dir, upstream, local) = upstream_and_clone();
+        commit_file(&upstream, "README.md", "two");
+        let local_dir = dir.path().join("local");
+
+        let status = update(&local_dir, &mut FetchOptions::new(), None).unwrap();
+        assert!(matches!(status, SyncStatus::Updated));
+        assert_eq!(
+            local.head().unwrap().target(),
+            upstream.head().unwrap().target()
+        );
+        let status = update(&local_dir, &mut FetchOptions::new(), None).unwrap();
+        assert!(matches!(status, SyncStatus::UpToDate));
+    }
+
+    #[test]
+    fn update_skips_dirty_working_tree() {
+        let (dir, upstream, local) = upstream_and_clone();
+        commit_file(&upstream, "README.md", "two");
+        write(dir.path().join("local/README.md"), "local edit").unwrap();
+        let before = local.head().unwrap().target();
+
+        let status = update(&dir.path().join("local"), &mut FetchOptions::new(), None).unwrap();
+        assert!(matches!(status, SyncStatus::Dirty));
+        assert_eq!(local.head().unwrap().target(), before);
+    }
+
+    #[test]
+    fn update_reports_divergence() {
+        let (dir, upstream, local) = upstream_and_clone();
+        commit_file(&upstream, "upstream.md", "upstream");
+        commit_file(&local, "local.md", "local");
+
+        let status = update(&dir.path().join("local"), &mut FetchOptions::new(), None).unwrap();
+        assert!(matches!(status, SyncStatus::Diverged));
+    }
+
+    #[test]
+    fn clone_checks_out_pinned_tag() {
+        let (dir, upstream, _) = upstream_and_clone();
+        let tagged = upstream.head().unwrap().target().unwrap();
+        upstream
+            .tag_lightweight("v1", &upstream.find_object(tagged, None).unwrap(), false)
+            .unwrap();
+        commit_file(&upstream, "README.md", "two");
+
+        let pin = Pin::Tag("v1".to_string());
+        let pinned_dir = dir.path().join("pinned");
+        let status = clone(
+            dir.path().join("upstream").to_str().unwrap(),
+            &pinned_dir,
+            FetchOptions::new(),
+            Some(&pin),
+        )
+        .unwrap();
+        assert!(matches!(status, SyncStatus::Cloned));
+        let pinned = Repository::open(&pinned_dir).unwrap();
+        assert!(pinned.head_detached().unwrap());
+        assert_eq!(pinned.head().unwrap().target(), Some(tagged));
+    }
+
+    #[test]
+    fn update_switches_to_pinned_branch() {
+        let (dir, upstream, local) = upstream_and_clone();
+        let head = upstream.head().unwrap().peel_to_commit().unwrap();
+        upstream.branch("release", &head, false).unwrap();
+        let pin = Pin::Branch("release".to_string());
+
+        let status = update(
+            &dir.path().join("local"),
+            &mut FetchOptions::new(),
+            Some(&pin),
+        )
+        .unwrap();
+        assert!(matches!(status, SyncStatus::Updated));
+        assert_eq!(local.head().unwrap().shorthand(), Some("release"));
+        let status = update(
+            &dir.path().join("local"),
+            &mut FetchOptions::new(),
+            Some(&pin),
+        )
+        .unwrap();
+        assert!(matches!(status, SyncStatus::UpToDate));
+    }
+
+    #[test]
+    fn update_fails_on_unreachable_rev() {
+        let (dir, _, _) = upstream_and_clone();
+        let pin = Pin::Rev("0123456789abcdef0123456789abcdef01234567".to_string());
+
+        let error = update(
+            &dir.path().join("local"),
+            &mut FetchOptions::new(),
+            Some(&pin),
+        )
+        .unwrap_err();
+        assert!(error.message().contains("not reachable"));
+    }
+
+    #[test]
+    fn unsaved_work_lists_everything_that_would_be_lost() {
+        let (dir, _, mut local) = upstream_and_clone();
+        assert!(unsaved_work(&dir.path().join("local")).unwrap().is_empty());
+
+        commit_file(&local, "local.md", "unpushed");
+        let head = local.head().unwrap().peel_to_commit().unwrap();
+        local.branch("experiment", &head, false).unwrap();
+        drop(head);
+        write(dir.path().join("local/README.md"), "stashed").unwrap();
+        let signature = git2::Signature::now("gitspace", "gitspace@example.com").unwrap();
+        local.stash_save(&signature, "wip", None).unwrap();
+        write(dir.path().join("local/scratch.md"), "untracked").unwrap();
+
+        let reasons = unsaved_work(&dir.path().join("local")).unwrap();
+        assert_eq!(reasons.len(), 4, "{:?}", reasons);
+        assert!(reasons.contains(&"1 uncommitted change(s)".to_string()));
+        assert!(reasons.contains(&"1 stash(es)".to_string()));
+        assert!(reasons.contains(&"branch experiment only exists locally".to_string()));
+        assert!(reasons
+            .iter()
+            .any(|reason| reason.ends_with("1 unpushed commit(s)")));
+    }
+
+    #[test]
+    fn unsaved_work_includes_commits_on_a_detached_head() {
+        let (dir, _, local) = upstream_and_clone();
+        let pinned = local.head().unwrap().target().unwrap();
+        local.set_head_detached(pinned).unwrap();
+        assert!(unsaved_work(&dir.path().join("local")).unwrap().is_empty());
+
+        commit_file(&local, "detached.md", "unreachable");
+        let reasons = unsaved_work(&dir.path().join("local")).unwrap();
+        assert_eq!(reasons, vec!["detached HEAD has commits on no branch"]);
+    }
+
+    #[test]
+    fn export_writes_head_without_git_metadata() {
+        let (dir, _, local) = upstream_and_clone();
+        write(dir.path().join("local/README.md"), "local edit").unwrap();
+        let dest = dir.path().join("copy");
+
+        export(&dir.path().join("local"), &dest).unwrap();
+        assert_eq!(
+            std::fs::read_to_string(dest.join("README.md")).unwrap(),
+            "one"
+        );
+        assert!(!dest.join(".git").exists());
+        assert!(is_dirty(&local).unwrap());
+        assert!(matches_export(&dir.path().join("local"), &dest).unwrap());
+
+        write(dest.join("README.md"), "edited in the copy").unwrap();
+        assert!(!matches_export(&dir.path().join("local"), &dest).unwrap());
+        write(dest.join("README.md"), "one").unwrap();
+        write(dest.join("extra.md"), "not in HEAD").unwrap();
+        assert!(!matches_export(&dir.path().join("local"), &dest).unwrap());
+    }
+}
@@ -0,0 +1,82 @@
+//! Managed block in the workspace .gitignore, so clones and generated links never get committed
+use std::fs::{read_to_string, write};
+use std::io::ErrorKind;
+use std::path::Path;
+
+use crate::error::{GitspaceError, Result};
+
+const BEGIN: &str = "# >>> gitspace (managed block, edits will be overwritten) >>>";
+const END: &str = "# <<< gitspace <<<";
+
+/// `existing` .gitignore contents with the managed block replaced by `entries`
+/// the block is appended when there isn't one yet; lines outside it are kept as they are
+pub fn render(existing: &str, entries: &[String]) -> String {
+    let lines: Vec<&str> = existing.lines().collect();
+    let begin = lines.iter().position(|line| line.trim() == BEGIN);
+    let end = begin.and_then(|begin| {
+        lines[begin..]
+            .iter()
+            .position(|line| line.trim() == END)
+            .map(|end| begin + end)
+    });
+    let (mut before, after) = match (begin, end) {
+        (Some(begin), Some(end)) => (lines[..begin].to_vec(), lines[end + 1..].to_vec()),
+        _ => {
+            let mut before = lines.to_vec();
+            if before.last().is_some_and(|line| !line.trim().is_empty()) {
+                before.push("");
+            }
+            (before, Vec::new())
+        }
+    };
+    before.push(BEGIN);
+    before.extend(entries.iter().map(String::as_str));
+    before.push(END);
+    before.extend(after);
+    before.join("\n") + "\n"
+}
+
+/// Rewrite the managed block of the .gitignore at `gitignore_path`, creating the file if needed
+/// return whether the file changed
+pub fn write_block(gitignore_path: &Path, entries: &[String]) -> Result<bool> {
+    let existing = match read_to_string(gitignore_path) {
+        Ok(existing) => existing,
+        Err(e) if e.kind() == ErrorKind::NotFound => String::new(),
+        Err(e) => return Err(GitspaceError::filesystem(gitignore_path)(e)),
+    };
+    let updated = render(&existing, entries);
+    if updated == existing {
+        return Ok(false);
+    }
+    write(gitignore_path, updated).map_err(GitspaceError::filesystem(gitignore_path))?;
+    Ok(true)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn block_is_replaced_without_touching_user_entries() {
+        let entries = vec!["/.space/repositories/".to_string(), "/api".to_string()];
+        let created = render("node_modules/\n.env", &entries);
+        assert_eq!(
+            created,
+            format!(
+                "node_modules/\n.env\n\n{}\n/.space/repositories/\n/api\n{}\n",
+                BEGIN, END
+            )
+        );
+        assert_eq!(render(&created, &entries), created);
+
+        let edited = created.replace(".env", ".env\n*.log") + "dist/\n";
+        let updated = render(&edited, &entries[..1]);
+        assert_eq!(
+            updated,
+            format!(
+                "node_modules/\n.env\n*.log\n\n{}\n/.space/repositories/\n{}\ndist/\n",
+                BEGIN, END
+            )
+        );
+    }
+}
@@ -0,0 +1,32 @@
+//! Gitspace
+//!
+//! Clone, sync and symlink the repositories listed in a workspace's `.space/config.json`.
+//! The `gitspace` binary is a thin front-end over [`Workspace`]:
+//!
+//! ```no_run
+//! use gitspace::{SyncOptions, Workspace};
+//!
+//! let workspace = Workspace::discover()?;
+//! for (project, status) in workspace.sync(&SyncOptions::default())?.repos {
+//!     println!("{}: {}", project, status);
+//! }
+//! # Ok::<(), gitspace::GitspaceError>(())
+//! ```
+pub mod alias;
+pub mod completions;
+pub mod config;
+pub mod error;
+pub mod exec;
+pub mod format;
+pub mod git;
+pub mod ignore;
+pub mod lock;
+mod pool;
+pub mod status;
+pub mod symlinks;
+pub mod trash;
+pub mod validate;
+pub mod workspace;
+
+pub use error::{GitspaceError, Result};
+pub use workspace::{CleanTarget, SymlinkOptions, SyncOptions, Workspace};
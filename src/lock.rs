@@ -0,0 +1,86 @@
+//! Workspace lockfile recording the exact commit each repository was synced to
+use serde::{Deserialize, Serialize};
+use std::fs::{write, File};
+use std::io::BufReader;
+use std::path::{Path, PathBuf};
+
+use crate::error::{GitspaceError, Result};
+
+const LOCK: &str = "lock.json";
+
+/// A repository resolved to the commit it's checked out at
+#[derive(Debug, PartialEq, Eq, Serialize, Deserialize, Clone)]
+#[serde(rename_all = "camelCase")]
+pub struct LockedRepo {
+    pub namespace: String,
+    pub project: String,
+    /// URL of the `origin` remote
+    pub url: String,
+    /// Full name of the checked-out ref (eg. `refs/heads/main`); absent when HEAD is detached at a rev
+    #[serde(rename = "ref", default, skip_serializing_if = "Option::is_none")]
+    pub reference: Option<String>,
+    /// SHA of the commit HEAD points at
+    pub commit: String,
+}
+
+#[derive(Debug, PartialEq, Eq, Serialize, Deserialize, Clone, Default)]
+#[serde(rename_all = "camelCase")]
+pub struct Lockfile {
+    pub repositories: Vec<LockedRepo>,
+}
+
+impl Lockfile {
+    /// lock.json lives next to whichever config.json it was generated from
+    pub fn path(config_path: &Path) -> PathBuf {
+        config_path.with_file_name(LOCK)
+    }
+
+    /// Read a lock.json file
+    pub fn read(lock_path: &Path) -> Result<Lockfile> {
+        let file = File::open(lock_path).map_err(GitspaceError::config_io(lock_path))?;
+        serde_json::from_reader(BufReader::new(file))
+            .map_err(GitspaceError::config_parse(lock_path))
+    }
+
+    /// Write the lockfile as pretty-printed JSON
+    pub fn write(&self, lock_path: &Path) -> Result<()> {
+        let json =
+            serde_json::to_string_pretty(&self).map_err(GitspaceError::config_parse(lock_path))?;
+        write(lock_path, json).map_err(GitspaceError::config_io(lock_path))
+    }
+
+    /// Find the locked entry for a configured repository
+    pub fn find(&self, namespace: &str, project: &str) -> Option<&LockedRepo> {
+        self.repositories
+            .iter()
+            .find(|locked| locked.namespace == namespace && locked.project == project)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::TempDir;
+
+    #[test]
+    fn lockfile_round_trips() {
+        let dir = TempDir::new().unwrap();
+        let lock_path = Lockfile::path(&dir.path().join("config.json"));
+        assert_eq!(lock_path, dir.path().join(LOCK));
+
+        let lockfile = Lockfile {
+            repositories: vec![LockedRepo {
+                namespace: "capswan".to_string(),
+                project: "cli-gitspace".to_string(),
+                url: "git@github.com:capswan/cli-gitspace".to_string(),
+                reference: Some("refs/heads/main".to_string()),
+                commit: "0123456789abcdef0123456789abcdef01234567".to_string(),
+            }],
+        };
+        lockfile.write(&lock_path).unwrap();
+        let read_back = Lockfile::read(&lock_path).unwrap();
+        assert_eq!(read_back, lockfile);
+        assert!(read_back.find("capswan", "cli-gitspace").is_some());
+        assert!(read_back.find("partner", "cli-gitspace").is_none());
+    }
+}
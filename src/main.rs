@@ -1,11 +1,20 @@
 //! Gitspace
-use std::fmt::{Display};
-use std::fs::File;
-use std::io::prelude::*;
-use std::path::Path;
-use clap::{Parser, Subcommand};
-mod config;
-use config::{Config, PathType};
+use clap::{CommandFactory, Parser, Subcommand};
+use gitspace::alias::Shell;
+use gitspace::completions;
+use gitspace::config::{self, cwd};
+use gitspace::exec::{self, ExecOptions, ExecStatus};
+use gitspace::format::ConfigFormat;
+use gitspace::git::SyncStatus;
+use gitspace::symlinks::{self, ConflictPolicy, LinkMode, SymlinkReport};
+use gitspace::trash::{self, Trash, TrashEntry};
+use gitspace::workspace::{find_config, find_root, find_space_root};
+use gitspace::{
+    status, validate, CleanTarget, GitspaceError, Result, SymlinkOptions, SyncOptions, Workspace,
+};
+use std::path::{Path, PathBuf};
+use std::process::exit;
+use std::time::Duration;
 
 #[derive(Debug, Parser)]
 #[clap(author, version, about)]
@@ -13,6 +22,10 @@ struct Arguments {
     #[clap(short, long)]
     config_file: Option<String>,
 
+    /// Workspace root to use instead of searching up from the current directory
+    #[clap(short, long, global = true)]
+    workspace: Option<PathBuf>,
+
     #[clap(short, long)]
     ssh_key: Option<String>,
 
@@ -20,83 +33,461 @@ struct Arguments {
     cmd: SubCommand,
 }
 
-
 #[derive(Subcommand, Debug)]
 enum SubCommand {
     /// Generate a .space directory with default config.json
-    Init {},
-    /// Clone/update repositories specified in config.json
+    Init {
+        /// Language to write the config in: json, yaml or toml
+        #[clap(long, default_value = "json")]
+        format: ConfigFormat,
+    },
+    /// Clone/update repositories specified in config.json, then update .gitignore
     Sync {
         // /TODO: Allow users to put -s ~/.ssh/key_path at the end of the command by
         //migrating from CLI arg to Subcommand::Sync arg
+        /// Check out the exact commits recorded in lock.json; fails if any is unreachable
+        #[clap(long)]
+        locked: bool,
+        /// Number of repositories to clone/fetch at once; overrides `sync.jobs` in config.json
+        #[clap(short, long)]
+        jobs: Option<usize>,
+    },
+    /// Record the commit every cloned repository is at in lock.json
+    Lock {},
+    /// Print aliases that cd into each repository, eg. `source <(gitspace alias --shell zsh)`
+    Alias {
+        /// zsh, bash, fish, elvish or nushell
+        #[clap(long, default_value = "zsh")]
+        shell: Shell,
+    },
+    /// Print a completion script that also completes repository names, eg.
+    /// `source <(gitspace completions bash)`
+    Completions {
+        /// zsh, bash, fish, elvish or nushell
+        shell: Shell,
     },
-    /// Cleanup target path; defaults to cleaning up repositories directory
+    /// List every name `--repo` accepts, one per line; used by the completion scripts
+    Repos {},
+    /// Update the gitspace block of .gitignore to cover cloned repositories and every symlink
+    Ignore {},
+    /// Show branch, HEAD, local changes and ahead/behind upstream for every repository
+    Status {
+        /// Print machine-readable JSON instead of a table
+        #[clap(long)]
+        json: bool,
+        /// Only show these repositories (`project` or `namespace/project`); repeatable
+        #[clap(short, long = "repo", value_name = "REPO")]
+        repos: Vec<String>,
+    },
+    /// Run a shell command in every cloned repository
+    Exec {
+        /// Number of repositories to run the command in at once
+        #[clap(short, long, default_value_t = 1)]
+        parallel: usize,
+        /// Stop starting new repositories after the first failure
+        #[clap(long)]
+        fail_fast: bool,
+        /// Only run in these repositories (`project` or `namespace/project`); repeatable
+        #[clap(short, long = "repo", value_name = "REPO")]
+        repos: Vec<String>,
+        /// Command to run, eg. `gitspace exec -- git log -1`
+        #[clap(required = true, trailing_var_arg = true)]
+        command: Vec<String>,
+    },
+    /// Move target path to .space/trash; defaults to cleaning up repositories directory
     Clean {
         #[clap(short, long)]
         target: String,
+        /// Remove repositories even if they have uncommitted changes, stashes, unpushed commits
+        /// or local-only branches
+        #[clap(short, long)]
+        force: bool,
+    },
+    /// Symlink every repository into `paths.symlinks`, the workspace root by default
+    Symlink {
+        /// absolute, relative (survives moving the workspace) or copy (for tools that don't follow
+        /// symlinks); overrides `symlinks.mode` in config.json
+        #[clap(long)]
+        mode: Option<LinkMode>,
+        /// What to do when something else is where a link should go: error, skip, replace or
+        /// backup; overrides `symlinks.onConflict` in config.json
+        #[clap(long)]
+        on_conflict: Option<ConflictPolicy>,
+        /// Report missing, dangling, misdirected and extra links instead of creating them; exits
+        /// with 1 on drift
+        #[clap(long, conflicts_with = "repair")]
+        check: bool,
+        /// Fix drifted links; exits with 1 if any drift is left
+        #[clap(long)]
+        repair: bool,
+    },
+    /// Move a cleaned target back out of the trash; defaults to the most recent one
+    Restore {
+        /// Trash entry to restore, as shown by `trash list`
+        id: Option<String>,
+    },
+    /// Inspect or empty .space/trash
+    Trash {
+        #[clap(subcommand)]
+        cmd: TrashCommand,
+    },
+    /// Work with the workspace config itself
+    Config {
+        #[clap(subcommand)]
+        cmd: ConfigCommand,
+    },
+}
+
+#[derive(Subcommand, Debug)]
+enum ConfigCommand {
+    /// Rewrite the config in another language; the old file is moved to .space/trash
+    Convert {
+        /// json, yaml or toml
+        #[clap(long)]
+        to: ConfigFormat,
+    },
+    /// Report every problem in the config at once; exits with 3 if any is an error
+    Validate {},
+    /// Print the JSON Schema of the config, for editor validation and autocompletion
+    Schema {},
+}
+
+#[derive(Subcommand, Debug)]
+enum TrashCommand {
+    /// List trashed targets, oldest first
+    List {},
+    /// Permanently delete trashed targets
+    Purge {
+        /// Only delete entries older than this, eg. `12h`, `30d` or `2w`
+        #[clap(long, value_parser = trash::parse_age)]
+        older_than: Option<Duration>,
     },
-    Symlink {}
 }
 
 fn main() {
     let args = Arguments::parse();
+    if let Err(e) = run(args) {
+        eprintln!("🧱 {}", e);
+        exit(e.exit_code());
+    }
+}
+
+fn run(args: Arguments) -> Result<()> {
     //TODO: Remove config.json and .space path as options from config.json (Paths struct); easier to just assume
     //those paths; if user changes the config.json path has changed how would we read it anyways
     //unless they specify the --config_path everytime? And if they did, would we implement caching
     //to update the config with the new path? Could make sense, but for now targeting the golden
     //path case of "I run gitspace init && gitspace sync and it just works"
-    let config = Config::new();
-    match &args.cmd {
-        SubCommand::Init {} => {
-            // Create .gitspace and write the default template to it
-            let _ = &config.write_config();
+    if let SubCommand::Init { format } = &args.cmd {
+        // Create .space and write the default template to it; every other command needs an
+        // existing workspace
+        let root = args
+            .workspace
+            .clone()
+            .unwrap_or_else(|| PathBuf::from(cwd()));
+        let workspace = Workspace::init_with_format(root, *format)?;
+        println!("🧱 Initialized {}", workspace.config_path().display());
+        return Ok(());
+    }
+
+    if let SubCommand::Completions { shell } = &args.cmd {
+        print!("{}", completions::render(*shell, &mut Arguments::command()));
+        return Ok(());
+    }
+
+    if let SubCommand::Config { cmd } = &args.cmd {
+        match cmd {
+            ConfigCommand::Schema {} => {
+                println!("{}", config::schema());
+                return Ok(());
+            }
+            ConfigCommand::Validate {} => {
+                // Load without the collision check so every problem gets reported together
+                let workspace = Workspace::load(config_path(&args)?)?;
+                let ssh_key = args.ssh_key.as_deref().map(Path::new);
+                let warnings = workspace.validate(ssh_key)?;
+                println!("{}", validate::render(&warnings));
+                println!("🧱 {} is valid", workspace.config_path().display());
+                return Ok(());
+            }
+            ConfigCommand::Convert { .. } => {}
         }
-        SubCommand::Sync {} => {
-            //TODO: Write integration test to ensure config_file override works properly
-            let config_path = &args
-                .config_file
-                .unwrap_or_else(|| config.get_path_as_string(&PathType::Config));
+    }
 
-            //TODO: Extract this into separate function so it can be used in other subcommands
-            //without needing to rely on the default Config object
-            let config = Config::read_config_raw(Path::new(&config_path));
-            println!("{:#?}", &config);
+    if let SubCommand::Restore { .. } | SubCommand::Trash { .. } = &args.cmd {
+        // `clean` may have moved config.json to the trash, so don't require a loadable workspace
+        return run_trash(&args);
+    }
 
+    //TODO: Write integration test to ensure config_file override works properly
+    let workspace = match &args.cmd {
+        // `sync` validates the whole config itself, collisions included
+        SubCommand::Sync { .. } => Workspace::load(config_path(&args)?)?,
+        _ => Workspace::open(config_path(&args)?)?,
+    };
+    let config_path = workspace.config_path().display().to_string();
+    match &args.cmd {
+        SubCommand::Init { .. }
+        | SubCommand::Completions { .. }
+        | SubCommand::Restore { .. }
+        | SubCommand::Trash { .. } => {
+            unreachable!("handled before loading the workspace")
+        }
+        SubCommand::Sync { locked, jobs } => {
+            println!("{:#?}", workspace.config());
             println!("🧱 Config path: {:?}", &config_path);
             //TODO: Write integration test to ensure ssh_key config.json override works properly
-            let key_path = &args
+            let key_path = args
                 .ssh_key
-                .unwrap_or_else(|| String::from(&config.ssh.identity_file));
-                // .unwrap_or_else(|| String::from(&config.ssh.identity_file));
-
+                .clone()
+                .unwrap_or_else(|| String::from(&workspace.config().ssh.identity_file));
             println!("🧱 Key path: {:?}", key_path);
-            let _ = &config.clone_repos(Path::new(&key_path));
-        }
-        SubCommand::Symlink {} => {
-            //TODO: Allow users to specify a target symlink directory, default to CWD as root.
-            //Update Paths struct to include symlink path
 
-            let _ = Config::write_symlinks(&config.repositories);
+            let options = SyncOptions {
+                // Left unset without --ssh-key so validation checks ssh.identityFile instead
+                key_path: args.ssh_key.as_ref().map(PathBuf::from),
+                locked: *locked,
+                jobs: *jobs,
+            };
+            let report = workspace.sync(&options)?;
+            if !report.warnings.is_empty() {
+                println!("{}", validate::render(&report.warnings));
+            }
+            for (project, status) in &report.repos {
+                println!("🧱 {}: {}", project, status);
+            }
+            if !locked {
+                report_unlocked(&report.unlocked);
+                println!("🔒 Updated {}", workspace.lock_path().display());
+            }
+            report_gitignore(report.gitignore);
+            // Every repository has been reported already; exit with the first failure's code
+            if let Some(e) = report
+                .repos
+                .into_iter()
+                .find_map(|(_, status)| match status {
+                    SyncStatus::Failed(e) => Some(e),
+                    _ => None,
+                })
+            {
+                return Err(e);
+            }
+        }
+        SubCommand::Alias { shell } => {
+            print!("{}", workspace.aliases(*shell)?);
+        }
+        SubCommand::Repos {} => {
+            for name in workspace.repo_names() {
+                println!("{}", name);
+            }
+        }
+        SubCommand::Ignore {} => {
+            report_gitignore(workspace.ignore()?);
+        }
+        SubCommand::Lock {} => {
+            report_unlocked(&workspace.lock()?.1);
+            println!("🔒 Updated {}", workspace.lock_path().display());
         }
-        SubCommand::Clean { target } => match target.as_str() {
-            "space" | "s" => {
-                let _ = &config.rm_space();
+        SubCommand::Status { json, repos } => {
+            report_unknown(&workspace, repos);
+            let statuses = workspace.status(repos);
+            if *json {
+                println!("{}", serde_json::to_string_pretty(&statuses).unwrap());
+            } else {
+                println!("{}", status::render_table(&statuses));
             }
-            "config" | "c" => {
-                let _ = &config.rm_config();
+        }
+        SubCommand::Exec {
+            parallel,
+            fail_fast,
+            repos,
+            command,
+        } => {
+            let options = ExecOptions {
+                parallel: *parallel,
+                fail_fast: *fail_fast,
+            };
+            report_unknown(&workspace, repos);
+            let results = workspace.exec(repos, &exec::command_line(command), &options);
+            println!("{}", exec::render_summary(&results));
+            if results
+                .iter()
+                .any(|(_, status)| matches!(status, ExecStatus::Failed(_)))
+            {
+                exit(1);
             }
-            "repositories" | "r" => {
-                //TODO: Check nested directories for whether git directories have any uncommitted changes before removing
-                let _ = &config.rm_repositories();
+        }
+        SubCommand::Symlink {
+            mode,
+            on_conflict,
+            check,
+            repair,
+        } => {
+            let options = SymlinkOptions {
+                mode: *mode,
+                on_conflict: *on_conflict,
+            };
+            if *check || *repair {
+                if *repair {
+                    let report = workspace.repair_symlinks(&options)?;
+                    for removed in &report.removed {
+                        println!("🧱 Removed symlink {:?}", removed);
+                    }
+                    report_symlinks(&report);
+                }
+                let drift = workspace.check_symlinks(&options)?;
+                println!("{}", symlinks::render_drift(&drift));
+                if !drift.is_empty() {
+                    exit(1);
+                }
+            } else {
+                report_symlinks(&workspace.symlink(&options)?);
             }
-            "symlinks" | "l" => {
-                //TODO: Allow user to specify location of symlinks with a new flag on the Clean
-                //subcommand
-                let _ = &config.rm_symlinks();
+        }
+        SubCommand::Config {
+            cmd: ConfigCommand::Convert { to },
+        } => {
+            let converted = workspace.convert(*to)?;
+            println!(
+                "🧱 Config is now {} (the old one is in the trash)",
+                converted.display()
+            );
+        }
+        SubCommand::Config { .. } => unreachable!("handled before loading the workspace"),
+        SubCommand::Clean { target, force } => {
+            let report = workspace.clean(CleanTarget::from_arg(target), *force)?;
+            for (path, reason) in &report.kept {
+                println!("🧱 Leaving {:?}: {}", path, reason);
             }
-            _ => {
-                let _ = &config.rm_repositories();
+            if let Some(entry) = &report.trashed {
+                report_trashed(entry);
             }
-        },
+        }
+    }
+    Ok(())
+}
+
+/// Warn about `--repo` names that match nothing, on stderr so `status --json` stays parseable
+fn report_unknown(workspace: &Workspace, repos: &[String]) {
+    for name in workspace.unknown_repos(repos) {
+        eprintln!("🧱 No repository named {} in config", name);
+    }
+}
+
+fn report_trashed(entry: &TrashEntry) {
+    println!(
+        "🧱 Moved {} to trash (restore with `gitspace restore {}`)",
+        entry.target, entry.id
+    );
+}
+
+/// Print a `symlink` run's summary, then whatever it moved to the trash
+fn report_symlinks(report: &SymlinkReport) {
+    println!("{}", symlinks::render_summary(&report.links));
+    report.trashed.iter().for_each(report_trashed);
+}
+
+/// Repositories `lock` left out, eg. because they aren't cloned yet
+fn report_unlocked(unlocked: &[(String, String)]) {
+    for (project, reason) in unlocked {
+        println!("🧱 {}: not locked ({})", project, reason);
+    }
+}
+
+fn report_gitignore(gitignore: Option<PathBuf>) {
+    if let Some(path) = gitignore {
+        println!("🧱 Updated {}", path.display());
+    }
+}
+
+/// Config file picked by `--config-file`, `--workspace` or the current directory
+fn config_path(args: &Arguments) -> Result<PathBuf> {
+    match (&args.config_file, &args.workspace) {
+        (Some(config_file), _) => Ok(PathBuf::from(config_file)),
+        (None, Some(root)) => {
+            find_config(root).ok_or_else(|| GitspaceError::NotAWorkspace(root.to_path_buf()))
+        }
+        (None, None) => {
+            let cwd = PathBuf::from(cwd());
+            find_root(&cwd)
+                .and_then(|root| find_config(&root))
+                .ok_or(GitspaceError::NotAWorkspace(cwd))
+        }
+    }
+}
+
+fn run_trash(args: &Arguments) -> Result<()> {
+    let trash = match (&args.config_file, &args.workspace) {
+        (Some(config_file), _) => Workspace::open(config_file)?.trash(),
+        (None, Some(root)) => Trash::at(root),
+        (None, None) => {
+            let cwd = PathBuf::from(cwd());
+            Trash::at(find_space_root(&cwd).ok_or(GitspaceError::NotAWorkspace(cwd))?)
+        }
+    };
+    match &args.cmd {
+        SubCommand::Restore { id } => {
+            let entry = trash.restore(id.as_deref())?;
+            println!("🧱 Restored {} from trash ({})", entry.target, entry.id);
+        }
+        SubCommand::Trash {
+            cmd: TrashCommand::List {},
+        } => {
+            let entries = trash.list()?;
+            if entries.is_empty() {
+                println!("🧱 Trash is empty");
+            }
+            for entry in entries {
+                println!("{}\t{} ago\t{}", entry.id, entry.age(), entry.target);
+            }
+        }
+        SubCommand::Trash {
+            cmd: TrashCommand::Purge { older_than },
+        } => {
+            let purged = trash.purge(*older_than)?;
+            println!("🧱 Purged {} trash entries", purged.len());
+        }
+        _ => unreachable!("only restore and trash are handled here"),
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn completions_complete_every_repo_option() {
+        let with_repo: Vec<String> = Arguments::command()
+            .get_subcommands()
+            .filter(|sub| {
+                sub.get_arguments()
+                    .any(|arg| arg.get_long() == Some("repo"))
+            })
+            .map(|sub| sub.get_name().to_string())
+            .collect();
+        assert_eq!(with_repo, ["status", "exec"]);
+        let script = |shell| completions::render(shell, &mut Arguments::command());
+        let count = |script: &str, pattern: &str| script.matches(pattern).count();
+
+        // `-r` and `--repo` are listed separately
+        let zsh = script(Shell::Zsh);
+        assert_eq!(count(&zsh, ":REPO:_gitspace_repos'"), 2 * with_repo.len());
+        assert_eq!(count(&zsh, ":REPO:_default'"), 0);
+        let bash = script(Shell::Bash);
+        let repos = r#"compgen -W "$(gitspace repos 2>/dev/null)""#;
+        assert_eq!(count(&bash, repos), 2 * with_repo.len());
+
+        let fish = script(Shell::Fish);
+        for name in &with_repo {
+            let option = format!("using_subcommand {}\" -s r -l repo ", name);
+            let line = fish.lines().find(|line| line.contains(&option)).unwrap();
+            assert!(line.ends_with(r#"-f -a "(gitspace repos 2>/dev/null)""#));
+        }
+        let nushell = script(Shell::Nushell);
+        let completed = r#"--repo(-r): string@"nu-complete gitspace repos""#;
+        assert_eq!(count(&nushell, completed), with_repo.len());
+        assert_eq!(count(&nushell, "--repo(-r): string"), with_repo.len());
+        assert!(script(Shell::Elvish).contains("has-value [-r --repo]"));
     }
 }
@@ -0,0 +1,64 @@
+//! Bounded worker pool shared by `sync` and `exec`
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Mutex;
+use std::thread;
+
+/// Run `work` over every item on at most `jobs` threads
+/// return the results in the same order as `items`, regardless of completion order
+pub fn run<T, R, F>(items: &[T], jobs: usize, work: F) -> Vec<R>
+where
+    T: Sync,
+    R: Send,
+    F: Fn(&T) -> R + Sync,
+{
+    let results: Mutex<Vec<Option<R>>> = Mutex::new(items.iter().map(|_| None).collect());
+    let next = AtomicUsize::new(0);
+
+    thread::scope(|scope| {
+        for _ in 0..jobs.clamp(1, items.len().max(1)) {
+            scope.spawn(|| loop {
+                let i = next.fetch_add(1, Ordering::SeqCst);
+                let Some(item) = items.get(i) else {
+                    break;
+                };
+                let result = work(item);
+                results.lock().unwrap()[i] = Some(result);
+            });
+        }
+    });
+
+    results
+        .into_inner()
+        .unwrap()
+        .into_iter()
+        .map(|result| result.expect("every item is claimed by exactly one worker"))
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+
+    #[test]
+    fn results_keep_input_order_within_job_limit() {
+        let running = AtomicUsize::new(0);
+        let peak = AtomicUsize::new(0);
+        let items: Vec<u64> = (0..12).collect();
+
+        let results = run(&items, 3, |item| {
+            let now = running.fetch_add(1, Ordering::SeqCst) + 1;
+            peak.fetch_max(now, Ordering::SeqCst);
+            // Later items finish first so ordering can't be an accident
+            thread::sleep(Duration::from_millis(12 - item));
+            running.fetch_sub(1, Ordering::SeqCst);
+            item * 2
+        });
+
+        assert_eq!(
+            results,
+            items.iter().map(|item| item * 2).collect::<Vec<u64>>()
+        );
+        assert!(peak.load(Ordering::SeqCst) <= 3);
+    }
+}
@@ -0,0 +1,203 @@
+//! Branch, working tree and upstream state of every configured repository
+use git2::{BranchType, Repository, Status, StatusOptions};
+use serde::Serialize;
+use std::path::Path;
+
+/// Snapshot of a single repository, serialized as-is for `status --json`
+#[derive(Debug, PartialEq, Eq, Serialize, Clone, Default)]
+#[serde(rename_all = "camelCase")]
+pub struct RepoStatus {
+    pub namespace: String,
+    pub project: String,
+    /// Whether the repository exists under the repositories directory
+    pub cloned: bool,
+    /// Checked-out branch; `None` when HEAD is detached
+    pub branch: Option<String>,
+    /// Short SHA of HEAD
+    pub head: Option<String>,
+    /// Staged or unstaged changes to tracked files
+    pub modified: usize,
+    pub untracked: usize,
+    /// Commits ahead of/behind the upstream branch; `None` without an upstream
+    pub ahead: Option<usize>,
+    pub behind: Option<usize>,
+    /// Set when the repository exists but couldn't be read
+    pub error: Option<String>,
+}
+
+impl RepoStatus {
+    /// Inspect the repository cloned at `repo_dir`
+    pub fn inspect(namespace: &str, project: &str, repo_dir: &Path) -> RepoStatus {
+        let status = RepoStatus {
+            namespace: namespace.to_string(),
+            project: project.to_string(),
+            ..Default::default()
+        };
+        if !repo_dir.exists() {
+            return status;
+        }
+        match read(repo_dir, status.clone()) {
+            Ok(status) => status,
+            Err(e) => RepoStatus {
+                cloned: true,
+                error: Some(e.message().to_string()),
+                ..status
+            },
+        }
+    }
+}
+
+fn read(repo_dir: &Path, mut status: RepoStatus) -> Result<RepoStatus, git2::Error> {
+    let repo = Repository::open(repo_dir)?;
+    status.cloned = true;
+
+    let mut options = StatusOptions::new();
+    options.include_untracked(true).include_ignored(false);
+    for entry in repo.statuses(Some(&mut options))?.iter() {
+        if entry.status().contains(Status::WT_NEW) {
+            status.untracked += 1;
+        } else {
+            status.modified += 1;
+        }
+    }
+
+    // A freshly initialised repository has no HEAD commit yet
+    let head = match repo.head() {
+        Ok(head) => head,
+        Err(_) => return Ok(status),
+    };
+    let head_oid = head.peel_to_commit()?.id();
+    status.head = Some(head_oid.to_string()[..7].to_string());
+    if !head.is_branch() {
+        return Ok(status);
+    }
+    let branch_name = head.shorthand().unwrap_or_default();
+    status.branch = Some(branch_name.to_string());
+
+    let upstream = repo
+        .find_branch(branch_name, BranchType::Local)?
+        .upstream()
+        .ok()
+        .and_then(|upstream| upstream.get().target());
+    if let Some(upstream_oid) = upstream {
+        let (ahead, behind) = repo.graph_ahead_behind(head_oid, upstream_oid)?;
+        status.ahead = Some(ahead);
+        status.behind = Some(behind);
+    }
+    Ok(status)
+}
+
+/// Render statuses as a compact, column-aligned table
+pub fn render_table(statuses: &[RepoStatus]) -> String {
+    let header = [
+        "REPOSITORY",
+        "BRANCH",
+        "HEAD",
+        "MODIFIED",
+        "UNTRACKED",
+        "UPSTREAM",
+    ];
+    let rows: Vec<Vec<String>> = statuses
+        .iter()
+        .map(|status| {
+            let project = status.project.to_string();
+            if !status.cloned {
+                return vec![project, "not cloned".to_string()];
+            }
+            if let Some(error) = &status.error {
+                return vec![project, format!("error: {}", error)];
+            }
+            let upstream = match (status.ahead, status.behind) {
+                (Some(0), Some(0)) => "up to date".to_string(),
+                (Some(ahead), Some(behind)) => format!("+{} -{}", ahead, behind),
+                _ => "-".to_string(),
+            };
+            vec![
+                project,
+                status
+                    .branch
+                    .clone()
+                    .unwrap_or_else(|| "(detached)".to_string()),
+                status.head.clone().unwrap_or_else(|| "-".to_string()),
+                status.modified.to_string(),
+                status.untracked.to_string(),
+                upstream,
+            ]
+        })
+        .collect();
+
+    // Rows that only carry a message don't widen the columns they spill over
+    let mut widths: Vec<usize> = header.iter().map(|column| column.len()).collect();
+    rows.iter()
+        .filter(|row| row.len() == header.len())
+        .for_each(|row| {
+            row.iter()
+                .enumerate()
+                .for_each(|(i, cell)| widths[i] = widths[i].max(cell.chars().count()))
+        });
+    widths[0] = rows
+        .iter()
+        .map(|row| row[0].chars().count())
+        .fold(widths[0], usize::max);
+
+    let header: Vec<String> = header.iter().map(|column| column.to_string()).collect();
+    std::iter::once(&header)
+        .chain(rows.iter())
+        .map(|row| {
+            row.iter()
+                .enumerate()
+                .map(|(i, cell)| format!("{:width$}", cell, width = widths[i]))
+                .collect::<Vec<String>>()
+                .join("  ")
+                .trim_end()
+                .to_string()
+        })
+        .collect::<Vec<String>>()
+        .join("\n")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::git::tests::{commit_file, upstream_and_clone};
+    use std::fs::write;
+
+    #[test]
+    fn inspect_counts_changes_and_upstream() {
+        let (dir, upstream, local) = upstream_and_clone();
+        commit_file(&upstream, "README.md", "two");
+        commit_file(&local, "local.md", "local");
+        local
+            .find_remote("origin")
+            .unwrap()
+            .fetch(&[] as &[&str], None, None)
+            .unwrap();
+        write(dir.path().join("local/README.md"), "edited").unwrap();
+        write(dir.path().join("local/new.md"), "new").unwrap();
+
+        let status = RepoStatus::inspect("capswan", "local", &dir.path().join("local"));
+        assert!(status.cloned);
+        assert_eq!(
+            status.branch,
+            local.head().unwrap().shorthand().map(String::from)
+        );
+        assert_eq!(status.modified, 1);
+        assert_eq!(status.untracked, 1);
+        assert_eq!((status.ahead, status.behind), (Some(1), Some(1)));
+    }
+
+    #[test]
+    fn missing_repository_is_not_cloned() {
+        let (dir, _, _) = upstream_and_clone();
+        let statuses = vec![
+            RepoStatus::inspect("capswan", "local", &dir.path().join("local")),
+            RepoStatus::inspect("capswan", "cli-ftr", &dir.path().join("cli-ftr")),
+        ];
+        assert!(!statuses[1].cloned);
+
+        let table = render_table(&statuses);
+        assert!(table.starts_with("REPOSITORY"));
+        assert!(table.contains("up to date"));
+        assert!(table.lines().last().unwrap().ends_with("not cloned"));
+    }
+}
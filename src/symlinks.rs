@@ -0,0 +1,393 @@
+//! Symlinks from the workspace into the repositories directory, and the record of which
+//! links gitspace created so cleaning never touches anyone else's
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+use std::fmt::{self, Display};
+use std::fs::{create_dir_all, write, File};
+use std::io::{BufReader, ErrorKind};
+use std::path::{Component, Path, PathBuf};
+use std::str::FromStr;
+
+use crate::error::{GitspaceError, Result};
+use crate::trash::TrashEntry;
+
+const SYMLINKS: &str = "symlinks.json";
+
+/// What `write_symlinks` does when something other than the expected link is in the way
+#[derive(Debug, PartialEq, Eq, Serialize, Deserialize, JsonSchema, Clone, Copy, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum ConflictPolicy {
+    /// Leave it alone and fail once every repository has been visited
+    #[default]
+    Error,
+    /// Leave it alone
+    Skip,
+    /// Remove a symlink, or move a file or directory to the trash, then link
+    Replace,
+    /// Rename it to `<name>.bak`, then link
+    Backup,
+}
+
+impl Display for ConflictPolicy {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ConflictPolicy::Error => write!(f, "error"),
+            ConflictPolicy::Skip => write!(f, "skip"),
+            ConflictPolicy::Replace => write!(f, "replace"),
+            ConflictPolicy::Backup => write!(f, "backup"),
+        }
+    }
+}
+
+impl FromStr for ConflictPolicy {
+    type Err = String;
+
+    fn from_str(policy: &str) -> std::result::Result<Self, Self::Err> {
+        match policy {
+            "error" => Ok(ConflictPolicy::Error),
+            "skip" => Ok(ConflictPolicy::Skip),
+            "replace" => Ok(ConflictPolicy::Replace),
+            "backup" => Ok(ConflictPolicy::Backup),
+            _ => Err(format!(
+                "unknown conflict policy {} (use error, skip, replace or backup)",
+                policy
+            )),
+        }
+    }
+}
+
+/// How `write_symlinks` makes each repository available in the workspace
+#[derive(Debug, PartialEq, Eq, Serialize, Deserialize, JsonSchema, Clone, Copy, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum LinkMode {
+    /// Symlink to the absolute path of the clone
+    #[default]
+    Absolute,
+    /// Symlink relative to the link's directory, so the workspace can be moved or mounted elsewhere
+    Relative,
+    /// Plain copy of the checked-out files, for tools that don't follow symlinks
+    Copy,
+}
+
+impl Display for LinkMode {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            LinkMode::Absolute => write!(f, "absolute"),
+            LinkMode::Relative => write!(f, "relative"),
+            LinkMode::Copy => write!(f, "copy"),
+        }
+    }
+}
+
+impl FromStr for LinkMode {
+    type Err = String;
+
+    fn from_str(mode: &str) -> std::result::Result<Self, Self::Err> {
+        match mode {
+            "absolute" => Ok(LinkMode::Absolute),
+            "relative" => Ok(LinkMode::Relative),
+            "copy" => Ok(LinkMode::Copy),
+            _ => Err(format!(
+                "unknown symlink mode {} (use absolute, relative or copy)",
+                mode
+            )),
+        }
+    }
+}
+
+/// Where links go for repositories that don't set their own `symlink` path
+#[derive(Debug, PartialEq, Eq, Serialize, Deserialize, JsonSchema, Clone, Copy, Default)]
+#[serde(rename_all = "kebab-case")]
+pub enum SymlinkLayout {
+    /// `<project>`
+    #[default]
+    Flat,
+    /// `<namespace>/<project>`
+    ByNamespace,
+}
+
+/// Outcome of linking a single repository
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub enum SymlinkStatus {
+    Created,
+    /// gitspace's own copy was rewritten from the current checkout
+    Refreshed,
+    /// The expected link was already there
+    Unchanged,
+    /// Something else was in the way and was removed, or moved to the trash
+    Replaced,
+    /// Something else was in the way and was renamed to the given path
+    BackedUp(PathBuf),
+    /// Something else is in the way and was left alone
+    Conflict(String),
+}
+
+impl Display for SymlinkStatus {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            SymlinkStatus::Created => write!(f, "created"),
+            SymlinkStatus::Refreshed => write!(f, "refreshed"),
+            SymlinkStatus::Unchanged => write!(f, "unchanged"),
+            SymlinkStatus::Replaced => write!(f, "replaced"),
+            SymlinkStatus::BackedUp(backup) => write!(f, "replaced, backup at {:?}", backup),
+            SymlinkStatus::Conflict(reason) => write!(f, "conflict: {}", reason),
+        }
+    }
+}
+
+/// How a link on disk differs from what config.json expects
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub enum LinkDrift {
+    /// Nothing where the link should be
+    Missing,
+    /// A symlink whose target doesn't exist, eg. a repository that isn't cloned
+    Dangling(PathBuf),
+    /// A symlink to somewhere other than the repository
+    PointsElsewhere(PathBuf),
+    /// Something other than the expected link or copy is in the way
+    InTheWay(String),
+    /// A copy whose files differ from the repository's checked-out commit
+    Outdated,
+    /// A link gitspace created for a repository that is no longer in config.json
+    Extra,
+}
+
+impl Display for LinkDrift {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            LinkDrift::Missing => write!(f, "missing"),
+            LinkDrift::Dangling(target) => {
+                write!(f, "dangling ({:?} doesn't exist)", target)
+            }
+            LinkDrift::PointsElsewhere(target) => write!(f, "points to {:?}", target),
+            LinkDrift::InTheWay(reason) => write!(f, "{} in the way", reason),
+            LinkDrift::Outdated => write!(f, "copy differs from the repository"),
+            LinkDrift::Extra => write!(f, "no longer in config"),
+        }
+    }
+}
+
+/// Report of a `symlink --check`, one line per drifted link
+pub fn render_drift(drift: &[(String, LinkDrift)]) -> String {
+    let mut report = vec![match drift.len() {
+        0 => "🧱 Every link matches config.json".to_string(),
+        n => format!("🧱 {} link(s) drifted from config.json", n),
+    }];
+    drift
+        .iter()
+        .for_each(|(link, drift)| report.push(format!("   {}: {}", link, drift)));
+    report.join("\n")
+}
+
+/// What a `symlink` run did, for the caller to report
+#[derive(Debug, Default)]
+pub struct SymlinkReport {
+    /// Each project's outcome, in config order
+    pub links: Vec<(String, SymlinkStatus)>,
+    /// Stray links `--repair` deleted
+    pub removed: Vec<PathBuf>,
+    /// Entries holding whatever was moved out of the way, for `gitspace restore`
+    pub trashed: Vec<TrashEntry>,
+}
+
+/// Summary of a `write_symlinks` run, listing every link that wasn't simply created or kept
+pub fn render_summary(results: &[(String, SymlinkStatus)]) -> String {
+    let count = |matches: fn(&SymlinkStatus) -> bool| {
+        results.iter().filter(|(_, status)| matches(status)).count()
+    };
+    let mut summary = vec![format!(
+        "🧱 {} created, {} unchanged, {} replaced, {} conflicting",
+        count(|status| matches!(status, SymlinkStatus::Created | SymlinkStatus::Refreshed)),
+        count(|status| *status == SymlinkStatus::Unchanged),
+        count(|status| matches!(status, SymlinkStatus::Replaced | SymlinkStatus::BackedUp(_))),
+        count(|status| matches!(status, SymlinkStatus::Conflict(_))),
+    )];
+    results
+        .iter()
+        .filter(|(_, status)| {
+            !matches!(
+                status,
+                SymlinkStatus::Created | SymlinkStatus::Refreshed | SymlinkStatus::Unchanged
+            )
+        })
+        .for_each(|(project, status)| summary.push(format!("   {}: {}", project, status)));
+    summary.join("\n")
+}
+
+/// A symlink created by `write_symlinks`
+#[derive(Debug, PartialEq, Eq, Serialize, Deserialize, Clone)]
+#[serde(rename_all = "camelCase")]
+pub struct ManagedLink {
+    pub project: String,
+    /// Path of the link, relative to the workspace root
+    pub link: PathBuf,
+    /// Copies are directories rather than links, so they can only be recognised from here
+    #[serde(default)]
+    pub mode: LinkMode,
+}
+
+#[derive(Debug, PartialEq, Eq, Serialize, Deserialize, Clone, Default)]
+#[serde(rename_all = "camelCase")]
+pub struct SymlinkManifest {
+    pub links: Vec<ManagedLink>,
+}
+
+impl SymlinkManifest {
+    /// symlinks.json lives in the .space directory, wherever config.json was loaded from
+    pub fn path(space_path: &Path) -> PathBuf {
+        space_path.join(SYMLINKS)
+    }
+
+    /// Read symlinks.json; a workspace that never created links has an empty manifest
+    pub fn read(manifest_path: &Path) -> Result<SymlinkManifest> {
+        let file = match File::open(manifest_path) {
+            Ok(file) => file,
+            Err(e) if e.kind() == ErrorKind::NotFound => return Ok(SymlinkManifest::default()),
+            Err(e) => return Err(GitspaceError::config_io(manifest_path)(e)),
+        };
+        serde_json::from_reader(BufReader::new(file))
+            .map_err(GitspaceError::config_parse(manifest_path))
+    }
+
+    /// Write the manifest as pretty-printed JSON, creating the .space directory if needed
+    pub fn write(&self, manifest_path: &Path) -> Result<()> {
+        if let Some(space_path) = manifest_path.parent() {
+            create_dir_all(space_path).map_err(GitspaceError::config_io(space_path))?;
+        }
+        let json = serde_json::to_string_pretty(&self)
+            .map_err(GitspaceError::config_parse(manifest_path))?;
+        write(manifest_path, json).map_err(GitspaceError::config_io(manifest_path))
+    }
+
+    /// Record `link`, replacing any earlier entry for the same path
+    pub fn insert(&mut self, link: ManagedLink) {
+        self.links.retain(|managed| managed.link != link.link);
+        self.links.push(link);
+    }
+
+    pub fn get(&self, link: &Path) -> Option<&ManagedLink> {
+        self.links.iter().find(|managed| managed.link == link)
+    }
+
+    pub fn contains(&self, link: &Path) -> bool {
+        self.get(link).is_some()
+    }
+}
+
+/// Where the symlink at `link` points, resolved against the link's directory when relative
+/// `None` when `link` isn't a symlink
+pub fn resolve(link: &Path) -> Option<PathBuf> {
+    let target = link.read_link().ok()?;
+    Some(normalize(&match link.parent() {
+        Some(parent) if target.is_relative() => parent.join(target),
+        _ => target,
+    }))
+}
+
+/// Target of a relative symlink at `link` pointing to `target`
+pub fn relative_target(target: &Path, link: &Path) -> PathBuf {
+    link.parent()
+        .and_then(|parent| pathdiff::diff_paths(target, parent))
+        .unwrap_or_else(|| target.to_path_buf())
+}
+
+/// Drop `.` and fold `..` into the preceding component, without touching the filesystem
+/// (the link may be dangling)
+pub(crate) fn normalize(path: &Path) -> PathBuf {
+    let mut normalized = PathBuf::new();
+    for component in path.components() {
+        match component {
+            Component::CurDir => {}
+            Component::ParentDir
+                if matches!(
+                    normalized.components().next_back(),
+                    Some(Component::Normal(_))
+                ) =>
+            {
+                normalized.pop();
+            }
+            component => normalized.push(component),
+        }
+    }
+    normalized
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::TempDir;
+
+    #[test]
+    fn manifest_round_trips_and_defaults_to_empty() {
+        let dir = TempDir::new().unwrap();
+        let manifest_path = SymlinkManifest::path(&dir.path().join(".space"));
+        assert_eq!(
+            SymlinkManifest::read(&manifest_path).unwrap(),
+            SymlinkManifest::default()
+        );
+
+        let mut manifest = SymlinkManifest::default();
+        let link = ManagedLink {
+            project: "cli-gitspace".to_string(),
+            link: PathBuf::from("cli-gitspace"),
+            mode: LinkMode::Relative,
+        };
+        manifest.insert(link.clone());
+        manifest.insert(link);
+        assert_eq!(manifest.links.len(), 1);
+        manifest.write(&manifest_path).unwrap();
+        let read_back = SymlinkManifest::read(&manifest_path).unwrap();
+        assert!(read_back.contains(Path::new("cli-gitspace")));
+        assert_eq!(read_back, manifest);
+    }
+
+    #[test]
+    #[cfg(unix)]
+    fn relative_links_resolve_to_their_target() {
+        let dir = TempDir::new().unwrap();
+        let target = dir.path().join(".space/repositories/billing");
+        let link = dir.path().join("services/billing");
+        let relative = relative_target(&target, &link);
+        assert_eq!(relative, Path::new("../.space/repositories/billing"));
+
+        std::fs::create_dir_all(link.parent().unwrap()).unwrap();
+        std::os::unix::fs::symlink(&relative, &link).unwrap();
+        assert_eq!(resolve(&link), Some(target));
+        assert_eq!(resolve(dir.path()), None);
+    }
+
+    #[test]
+    fn drift_report_lists_every_link() {
+        assert_eq!(render_drift(&[]), "🧱 Every link matches config.json");
+        let drift = vec![
+            ("api".to_string(), LinkDrift::Missing),
+            ("old".to_string(), LinkDrift::Extra),
+        ];
+        assert_eq!(
+            render_drift(&drift),
+            "🧱 2 link(s) drifted from config.json\n   api: missing\n   old: no longer in config"
+        );
+    }
+
+    #[test]
+    fn summary_counts_every_outcome() {
+        assert_eq!("backup".parse(), Ok(ConflictPolicy::Backup));
+        assert!("overwrite".parse::<ConflictPolicy>().is_err());
+
+        let results = vec![
+            ("api".to_string(), SymlinkStatus::Created),
+            ("web".to_string(), SymlinkStatus::Unchanged),
+            (
+                "docs".to_string(),
+                SymlinkStatus::BackedUp(PathBuf::from("docs.bak")),
+            ),
+            (
+                "cli".to_string(),
+                SymlinkStatus::Conflict("directory in the way".to_string()),
+            ),
+        ];
+        let summary = render_summary(&results);
+        assert!(summary.starts_with("🧱 1 created, 1 unchanged, 1 replaced, 1 conflicting"));
+        assert_eq!(summary.lines().count(), 3);
+    }
+}
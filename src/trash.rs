@@ -0,0 +1,302 @@
+//! Reversible `clean`: removed paths are moved into `.space/trash/<id>/` instead of deleted
+use serde::{Deserialize, Serialize};
+use std::fs::{create_dir_all, remove_dir_all, rename, write, File};
+use std::io::{self, BufReader};
+use std::path::{Path, PathBuf};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use crate::error::{GitspaceError, Result};
+
+pub(crate) const TRASH: &str = "trash";
+const MANIFEST: &str = "manifest.json";
+const FILES: &str = "files";
+
+/// One `clean` run, recorded in `<id>/manifest.json`
+#[derive(Debug, PartialEq, Eq, Serialize, Deserialize, Clone)]
+#[serde(rename_all = "camelCase")]
+pub struct TrashEntry {
+    pub id: String,
+    /// Seconds since the unix epoch
+    pub created_at: u64,
+    /// What was cleaned, eg. `repositories`
+    pub target: String,
+    /// Trashed paths, relative to the workspace root; stored under `<id>/files/` at the same path
+    pub paths: Vec<PathBuf>,
+}
+
+impl TrashEntry {
+    /// Time since the entry was trashed, eg. `3h`
+    pub fn age(&self) -> String {
+        let seconds = now().saturating_sub(self.created_at);
+        match seconds {
+            s if s < 60 => format!("{}s", s),
+            s if s < 60 * 60 => format!("{}m", s / 60),
+            s if s < 60 * 60 * 24 => format!("{}h", s / (60 * 60)),
+            s => format!("{}d", s / (60 * 60 * 24)),
+        }
+    }
+
+    /// Position among the entries trashed in the same second: `<secs>` is 1, `<secs>-2` is 2, ...
+    fn sequence(&self) -> u32 {
+        self.id
+            .split_once('-')
+            .and_then(|(_, attempt)| attempt.parse().ok())
+            .unwrap_or(1)
+    }
+
+    fn read(entry_dir: &Path) -> Result<TrashEntry> {
+        let manifest = entry_dir.join(MANIFEST);
+        let file = File::open(&manifest).map_err(GitspaceError::filesystem(&manifest))?;
+        serde_json::from_reader(BufReader::new(file)).map_err(GitspaceError::config_parse(manifest))
+    }
+
+    fn write(&self, entry_dir: &Path) -> Result<()> {
+        let manifest = entry_dir.join(MANIFEST);
+        let json =
+            serde_json::to_string_pretty(&self).map_err(GitspaceError::config_parse(&manifest))?;
+        write(&manifest, json).map_err(GitspaceError::filesystem(&manifest))
+    }
+}
+
+/// The trash directory of a workspace
+#[derive(Debug, Clone)]
+pub struct Trash {
+    root: PathBuf,
+    dir: PathBuf,
+}
+
+impl Trash {
+    /// Trash kept in `dir`, restoring paths relative to the workspace `root`
+    pub fn new(root: impl Into<PathBuf>, dir: impl Into<PathBuf>) -> Trash {
+        let root = root.into();
+        let dir = root.join(dir.into());
+        Trash { root, dir }
+    }
+
+    /// Trash of the workspace rooted at `root`, ie. `<root>/.space/trash`
+    pub fn at(root: impl Into<PathBuf>) -> Trash {
+        Trash::new(root, Path::new(crate::config::GITSPACE).join(TRASH))
+    }
+
+    pub fn dir(&self) -> &Path {
+        &self.dir
+    }
+
+    /// Move `paths` into a new entry; they must be inside the workspace root
+    /// return the entry's manifest
+    pub fn put(&self, target: &str, paths: &[PathBuf]) -> Result<TrashEntry> {
+        let created_at = now();
+        let mut id = created_at.to_string();
+        let mut attempt = 1;
+        while self.dir.join(&id).exists() {
+            attempt += 1;
+            id = format!("{}-{}", created_at, attempt);
+        }
+        let entry_dir = self.dir.join(&id);
+        let files = entry_dir.join(FILES);
+
+        let mut entry = TrashEntry {
+            id,
+            created_at,
+            target: target.to_string(),
+            paths: Vec::new(),
+        };
+        for path in paths {
+            let path = self.root.join(path);
+            let relative = path
+                .strip_prefix(&self.root)
+                .map_err(|_| {
+                    let outside = io::Error::new(
+                        io::ErrorKind::InvalidInput,
+                        "only paths inside the workspace can be trashed",
+                    );
+                    GitspaceError::filesystem(&path)(outside)
+                })?
+                .to_path_buf();
+            path.symlink_metadata()
+                .map_err(GitspaceError::filesystem(&path))?;
+            let stored = files.join(&relative);
+            if let Some(parent) = stored.parent() {
+                create_dir_all(parent).map_err(GitspaceError::filesystem(parent))?;
+            }
+            rename(&path, &stored).map_err(GitspaceError::filesystem(&path))?;
+            entry.paths.push(relative);
+            // Keep the manifest current so a failure halfway can still be restored
+            entry.write(&entry_dir)?;
+        }
+        Ok(entry)
+    }
+
+    /// Every entry, oldest first
+    pub fn list(&self) -> Result<Vec<TrashEntry>> {
+        let entries = match self.dir.read_dir() {
+            Ok(entries) => entries,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(Vec::new()),
+            Err(e) => return Err(GitspaceError::filesystem(&self.dir)(e)),
+        };
+        let mut trashed = Vec::new();
+        for entry in entries {
+            let entry_dir = entry.map_err(GitspaceError::filesystem(&self.dir))?.path();
+            if entry_dir.join(MANIFEST).is_file() {
+                trashed.push(TrashEntry::read(&entry_dir)?);
+            }
+        }
+        trashed.sort_by_key(|entry| (entry.created_at, entry.sequence()));
+        Ok(trashed)
+    }
+
+    /// Move the paths of entry `id` (the most recent one when `None`) back where they were
+    /// nothing is moved if any of them would overwrite an existing path
+    pub fn restore(&self, id: Option<&str>) -> Result<TrashEntry> {
+        let entry = match id {
+            Some(id) => TrashEntry::read(&self.dir.join(id))?,
+            None => self.list()?.pop().ok_or_else(|| {
+                let empty = io::Error::new(io::ErrorKind::NotFound, "trash is empty");
+                GitspaceError::filesystem(&self.dir)(empty)
+            })?,
+        };
+        let entry_dir = self.dir.join(&entry.id);
+        if let Some(existing) = entry
+            .paths
+            .iter()
+            .map(|path| self.root.join(path))
+            .find(|path| path.symlink_metadata().is_ok())
+        {
+            let exists = io::Error::new(
+                io::ErrorKind::AlreadyExists,
+                "already exists; move it away before restoring",
+            );
+            return Err(GitspaceError::filesystem(existing)(exists));
+        }
+        for path in &entry.paths {
+            let original = self.root.join(path);
+            if let Some(parent) = original.parent() {
+                create_dir_all(parent).map_err(GitspaceError::filesystem(parent))?;
+            }
+            rename(entry_dir.join(FILES).join(path), &original)
+                .map_err(GitspaceError::filesystem(&original))?;
+        }
+        remove_dir_all(&entry_dir).map_err(GitspaceError::filesystem(&entry_dir))?;
+        Ok(entry)
+    }
+
+    /// Permanently delete entries trashed more than `older_than` ago (every entry when `None`)
+    /// return the deleted entries
+    pub fn purge(&self, older_than: Option<Duration>) -> Result<Vec<TrashEntry>> {
+        let cutoff = older_than.map(|age| now().saturating_sub(age.as_secs()));
+        let mut purged = Vec::new();
+        for entry in self.list()? {
+            if cutoff.is_none_or(|cutoff| entry.created_at < cutoff) {
+                let entry_dir = self.dir.join(&entry.id);
+                remove_dir_all(&entry_dir).map_err(GitspaceError::filesystem(&entry_dir))?;
+                purged.push(entry);
+            }
+        }
+        Ok(purged)
+    }
+}
+
+/// Parse a `--older-than` age such as `45m`, `12h`, `30d` or `2w`
+pub fn parse_age(age: &str) -> std::result::Result<Duration, String> {
+    let split = age.find(|c: char| !c.is_ascii_digit()).unwrap_or(age.len());
+    let (amount, unit) = age.split_at(split);
+    let amount: u64 = amount
+        .parse()
+        .map_err(|_| format!("{} doesn't start with a number", age))?;
+    let unit = match unit {
+        "s" => 1,
+        "m" => 60,
+        "h" => 60 * 60,
+        "d" | "" => 60 * 60 * 24,
+        "w" => 60 * 60 * 24 * 7,
+        _ => return Err(format!("unknown unit {} (use s, m, h, d or w)", unit)),
+    };
+    Ok(Duration::from_secs(amount * unit))
+}
+
+fn now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|now| now.as_secs())
+        .unwrap_or_default()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs::read_to_string;
+    use tempfile::TempDir;
+
+    #[test]
+    fn entries_from_the_same_second_keep_their_order() {
+        let dir = TempDir::new().unwrap();
+        let trash = Trash::at(dir.path());
+        // `-10` sorts before `-2` as text
+        let entries: Vec<TrashEntry> = (1..=11)
+            .map(|attempt| {
+                let id = match attempt {
+                    1 => "1700000000".to_string(),
+                    n => format!("1700000000-{}", n),
+                };
+                let entry = TrashEntry {
+                    id: id.to_string(),
+                    created_at: 1_700_000_000,
+                    target: "repositories".to_string(),
+                    paths: Vec::new(),
+                };
+                create_dir_all(trash.dir().join(&id)).unwrap();
+                entry.write(&trash.dir().join(&id)).unwrap();
+                entry
+            })
+            .collect();
+        assert_eq!(trash.list().unwrap(), entries);
+        assert_eq!(trash.restore(None).unwrap(), entries[10]);
+    }
+
+    #[test]
+    fn trashed_paths_can_be_restored() {
+        let dir = TempDir::new().unwrap();
+        let space = dir.path().join(crate::config::GITSPACE);
+        create_dir_all(space.join("repositories/api")).unwrap();
+        write(space.join("config.json"), "{}").unwrap();
+        let trash = Trash::at(dir.path());
+
+        let first = trash.put("config", &[space.join("config.json")]).unwrap();
+        let second = trash
+            .put("repositories", &[space.join("repositories")])
+            .unwrap();
+        assert_ne!(first.id, second.id);
+        assert!(!space.join("repositories").exists());
+        assert_eq!(trash.list().unwrap(), vec![first.clone(), second.clone()]);
+
+        // Without an id, the most recent entry comes back
+        assert_eq!(trash.restore(None).unwrap(), second);
+        assert!(space.join("repositories/api").is_dir());
+
+        write(space.join("config.json"), "{\"new\": true}").unwrap();
+        assert!(matches!(
+            trash.restore(Some(&first.id)),
+            Err(GitspaceError::Filesystem { .. })
+        ));
+        assert_eq!(
+            read_to_string(space.join("config.json")).unwrap(),
+            "{\"new\": true}"
+        );
+
+        assert!(trash
+            .purge(Some(parse_age("1d").unwrap()))
+            .unwrap()
+            .is_empty());
+        assert_eq!(trash.purge(None).unwrap(), vec![first]);
+        assert!(trash.list().unwrap().is_empty());
+    }
+
+    #[test]
+    fn ages_need_a_known_unit() {
+        assert_eq!(parse_age("90m"), Ok(Duration::from_secs(90 * 60)));
+        assert_eq!(parse_age("2w"), Ok(Duration::from_secs(14 * 24 * 60 * 60)));
+        assert_eq!(parse_age("7"), Ok(Duration::from_secs(7 * 24 * 60 * 60)));
+        assert!(parse_age("d").is_err());
+        assert!(parse_age("3y").is_err());
+    }
+}
@@ -0,0 +1,154 @@
+//! Problems in a config that parses but can't work, eg. empty project names or a broken cron
+use std::fmt::{self, Display};
+
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub enum Severity {
+    /// `sync` refuses to run
+    Error,
+    /// Worth fixing, but nothing is stopped
+    Warning,
+}
+
+impl Display for Severity {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Severity::Error => write!(f, "error"),
+            Severity::Warning => write!(f, "warning"),
+        }
+    }
+}
+
+/// One problem, pinned to the config field it's about, eg. `repositories[2].project`
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub struct Problem {
+    pub severity: Severity,
+    pub field: String,
+    pub message: String,
+}
+
+impl Problem {
+    pub fn error(field: impl Into<String>, message: impl Into<String>) -> Problem {
+        Problem {
+            severity: Severity::Error,
+            field: field.into(),
+            message: message.into(),
+        }
+    }
+
+    pub fn warning(field: impl Into<String>, message: impl Into<String>) -> Problem {
+        Problem {
+            severity: Severity::Warning,
+            field: field.into(),
+            message: message.into(),
+        }
+    }
+}
+
+impl Display for Problem {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}: {}: {}", self.severity, self.field, self.message)
+    }
+}
+
+/// One line per problem, then `🧱 N errors, N warnings`
+pub fn render(problems: &[Problem]) -> String {
+    let errors = problems
+        .iter()
+        .filter(|problem| problem.severity == Severity::Error)
+        .count();
+    let mut lines: Vec<String> = problems.iter().map(Problem::to_string).collect();
+    lines.push(format!(
+        "🧱 {} errors, {} warnings",
+        errors,
+        problems.len() - errors
+    ));
+    lines.join("\n")
+}
+
+/// Check a five-field cron expression (minute hour day-of-month month day-of-week)
+/// fields may be `*`, numbers, ranges, lists and steps, eg. `*/15 9-17 * * mon-fri`
+pub fn cron(expression: &str) -> Result<(), String> {
+    const FIELDS: [(&str, u32, u32); 5] = [
+        ("minute", 0, 59),
+        ("hour", 0, 23),
+        ("day of month", 1, 31),
+        ("month", 1, 12),
+        ("day of week", 0, 7),
+    ];
+    let fields: Vec<&str> = expression.split_whitespace().collect();
+    if fields.len() != FIELDS.len() {
+        return Err(format!(
+            "expected 5 fields (minute hour day-of-month month day-of-week), found {}",
+            fields.len()
+        ));
+    }
+    for (field, (name, min, max)) in fields.iter().zip(FIELDS) {
+        for part in field.split(',') {
+            let (range, step) = match part.split_once('/') {
+                Some((range, step)) => (range, Some(step)),
+                None => (part, None),
+            };
+            if step.is_some_and(|step| !step.parse::<u32>().is_ok_and(|step| step > 0)) {
+                return Err(format!("{} has an invalid step in {}", name, part));
+            }
+            if range == "*" {
+                continue;
+            }
+            let value = |value: &str| {
+                cron_value(name, value)
+                    .filter(|value| (min..=max).contains(value))
+                    .ok_or_else(|| {
+                        format!(
+                            "{} must be between {} and {}, not {}",
+                            name, min, max, value
+                        )
+                    })
+            };
+            match range.split_once('-') {
+                Some((start, end)) if value(start)? > value(end)? => {
+                    return Err(format!("{} range {} is backwards", name, range))
+                }
+                Some(_) => {}
+                None => {
+                    value(range)?;
+                }
+            }
+        }
+    }
+    Ok(())
+}
+
+/// A cron number, or a month/weekday name like `jan` or `mon`
+fn cron_value(field: &str, value: &str) -> Option<u32> {
+    const MONTHS: [&str; 12] = [
+        "jan", "feb", "mar", "apr", "may", "jun", "jul", "aug", "sep", "oct", "nov", "dec",
+    ];
+    const DAYS: [&str; 7] = ["sun", "mon", "tue", "wed", "thu", "fri", "sat"];
+    let named = |names: &[&str], first: u32| {
+        names
+            .iter()
+            .position(|name| name.eq_ignore_ascii_case(value))
+            .map(|position| position as u32 + first)
+    };
+    match field {
+        "month" => value.parse().ok().or_else(|| named(&MONTHS, 1)),
+        "day of week" => value.parse().ok().or_else(|| named(&DAYS, 0)),
+        _ => value.parse().ok(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn cron_fields_are_range_checked() {
+        assert_eq!(cron("30 0 * * *"), Ok(()));
+        assert_eq!(cron("*/15 9-17 1,15 jan-jun mon-fri"), Ok(()));
+        assert!(cron("30 0 * *").is_err());
+        assert!(cron("60 0 * * *").is_err());
+        assert!(cron("0 17-9 * * *").is_err());
+        assert!(cron("*/0 * * * *").is_err());
+        assert!(cron("0 0 * * funday").is_err());
+    }
+}
@@ -0,0 +1,787 @@
+//! A gitspace workspace: a loaded config.json plus the operations the CLI runs against it
+use std::env::current_dir;
+use std::fs::write;
+use std::io;
+use std::path::{Path, PathBuf};
+
+use crate::alias::{self, Shell};
+use crate::config::{CleanReport, Config, PathType, GITSPACE};
+use crate::error::{GitspaceError, Result};
+use crate::exec::{self, ExecOptions, ExecStatus};
+use crate::format::{ConfigFormat, CONFIG_FILES};
+use crate::git::SyncStatus;
+use crate::lock::Lockfile;
+use crate::status::RepoStatus;
+use crate::symlinks::{ConflictPolicy, LinkDrift, LinkMode, SymlinkReport};
+use crate::trash::Trash;
+use crate::validate::{Problem, Severity};
+
+/// What `clean` removes
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CleanTarget {
+    Space,
+    Config,
+    Repositories,
+    Symlinks,
+}
+
+impl CleanTarget {
+    /// Parse a `clean --target` value; anything unrecognised falls back to repositories
+    pub fn from_arg(target: &str) -> CleanTarget {
+        match target {
+            "space" | "s" => CleanTarget::Space,
+            "config" | "c" => CleanTarget::Config,
+            "symlinks" | "l" => CleanTarget::Symlinks,
+            _ => CleanTarget::Repositories,
+        }
+    }
+}
+
+/// How `symlink` should link repositories; unset fields fall back to the `symlinks` config
+#[derive(Debug, Clone, Default)]
+pub struct SymlinkOptions {
+    /// Absolute or relative symlinks, or plain copies
+    pub mode: Option<LinkMode>,
+    /// What to do when something else is where a link should go
+    pub on_conflict: Option<ConflictPolicy>,
+}
+
+/// How `sync` should treat the repositories it visits
+#[derive(Debug, Clone, Default)]
+pub struct SyncOptions {
+    /// SSH key to authenticate with; defaults to `ssh.identityFile`
+    pub key_path: Option<PathBuf>,
+    /// Check out the commits recorded in lock.json instead of the configured refs
+    pub locked: bool,
+    /// Number of repositories to work on at once; defaults to `sync.jobs`
+    pub jobs: Option<usize>,
+}
+
+/// What a `sync` run did, for the caller to report
+#[derive(Debug, Default)]
+pub struct SyncReport {
+    /// Config problems that didn't stop the sync
+    pub warnings: Vec<Problem>,
+    /// Each repository's project name alongside the outcome of syncing it, in config order
+    pub repos: Vec<(String, SyncStatus)>,
+    /// Repositories left out of lock.json, each with the reason
+    pub unlocked: Vec<(String, String)>,
+    /// The workspace .gitignore, when its managed block changed
+    pub gitignore: Option<PathBuf>,
+}
+
+#[derive(Debug, Clone)]
+pub struct Workspace {
+    config_path: PathBuf,
+    config: Config,
+}
+
+impl Workspace {
+    /// Create the .space layout in `root` and write the default config.json
+    /// This is the only operation that creates files in a directory that isn't a workspace yet
+    pub fn init(root: impl Into<PathBuf>) -> Result<Workspace> {
+        Workspace::init_with_format(root, ConfigFormat::Json)
+    }
+
+    /// Like [`Workspace::init`], writing the default config as `config.<format>`
+    pub fn init_with_format(root: impl Into<PathBuf>, format: ConfigFormat) -> Result<Workspace> {
+        let config = Config::default().with_format(format).with_root(root);
+        config.create_layout()?;
+        config.write_config()?;
+        Ok(Workspace {
+            config_path: PathBuf::from(config.get_path_as_string(&PathType::Config)),
+            config,
+        })
+    }
+
+    /// Open the workspace described by `config_path`
+    /// The root is the directory containing `.space`, or the config's own directory otherwise
+    pub fn open(config_path: impl Into<PathBuf>) -> Result<Workspace> {
+        let workspace = Workspace::load(config_path)?;
+        workspace.config.check_collisions()?;
+        Ok(workspace)
+    }
+
+    /// Like [`Workspace::open`], but without refusing configs whose repositories collide, so
+    /// `config validate` can report every problem
+    pub fn load(config_path: impl Into<PathBuf>) -> Result<Workspace> {
+        let config_path = config_path.into();
+        let config_dir = config_path.parent().unwrap_or_else(|| Path::new(""));
+        let root = match config_dir.file_name() {
+            Some(name) if name == GITSPACE => config_dir.parent().unwrap_or_else(|| Path::new("")),
+            _ => config_dir,
+        };
+        let config = Config::read_config_raw(&config_path)?.with_root(root);
+        Ok(Workspace {
+            config_path,
+            config,
+        })
+    }
+
+    /// Open the workspace rooted at `root`, ie. `<root>/.space/config.json` (or .yaml, .yml, .toml)
+    pub fn at(root: impl AsRef<Path>) -> Result<Workspace> {
+        let config_path = find_config(root.as_ref())
+            .ok_or_else(|| GitspaceError::NotAWorkspace(root.as_ref().to_path_buf()))?;
+        Workspace::open(config_path)
+    }
+
+    /// Open the nearest workspace containing the current directory
+    pub fn discover() -> Result<Workspace> {
+        let cwd = current_dir().map_err(GitspaceError::filesystem("."))?;
+        Workspace::discover_from(&cwd)
+    }
+
+    /// Open the nearest workspace containing `start`, walking up parent directories like git does
+    pub fn discover_from(start: &Path) -> Result<Workspace> {
+        let root =
+            find_root(start).ok_or_else(|| GitspaceError::NotAWorkspace(start.to_path_buf()))?;
+        Workspace::at(root)
+    }
+
+    /// Directory containing `.space`; symlinks are created here unless `paths.symlinks` is set
+    pub fn root(&self) -> PathBuf {
+        self.config.root()
+    }
+
+    pub fn config(&self) -> &Config {
+        &self.config
+    }
+
+    pub fn config_path(&self) -> &Path {
+        &self.config_path
+    }
+
+    /// Rewrite the config as `config.<format>` next to the current file, which goes to the trash
+    /// return the path of the converted config
+    pub fn convert(&self, format: ConfigFormat) -> Result<PathBuf> {
+        let converted = self.config_path.with_file_name(format.file_name());
+        if converted == self.config_path {
+            return Ok(converted);
+        }
+        if converted.exists() {
+            let exists = io::Error::new(
+                io::ErrorKind::AlreadyExists,
+                "already exists; move it away before converting",
+            );
+            return Err(GitspaceError::filesystem(converted)(exists));
+        }
+        let contents = format
+            .render(&self.config.clone().with_format(format))
+            .map_err(GitspaceError::config_parse(&converted))?;
+        write(&converted, contents).map_err(GitspaceError::config_io(&converted))?;
+        let name = self.config_path.file_name().unwrap_or_default();
+        self.config.move_to_trash(
+            &name.to_string_lossy(),
+            std::slice::from_ref(&self.config_path),
+        )?;
+        Ok(converted)
+    }
+
+    /// lock.json, next to config.json
+    pub fn lock_path(&self) -> PathBuf {
+        Lockfile::path(&self.config_path)
+    }
+
+    /// Clone or fast-forward every repository, refreshing lock.json unless syncing `locked`,
+    /// then update the managed block of .gitignore
+    /// failures are reported per repository rather than returned
+    pub fn sync(&self, options: &SyncOptions) -> Result<SyncReport> {
+        let mut problems = self.config.validate(options.key_path.as_deref());
+        // Every clone and fetch would fail without a key, so stop before any network work
+        problems
+            .iter_mut()
+            .filter(|problem| problem.field == "ssh.identityFile")
+            .for_each(|problem| problem.severity = Severity::Error);
+        let mut report = SyncReport {
+            warnings: fail_on_errors(problems)?,
+            ..SyncReport::default()
+        };
+        let key_path = options
+            .key_path
+            .clone()
+            .unwrap_or_else(|| PathBuf::from(&self.config.ssh.identity_file));
+        let jobs = options.jobs.unwrap_or_else(|| self.config.sync_jobs());
+        if options.locked {
+            let lockfile = Lockfile::read(&self.lock_path())?;
+            report.repos = self.config.sync_repos(&key_path, Some(&lockfile), jobs);
+        } else {
+            report.repos = self.config.sync_repos(&key_path, None, jobs);
+            report.unlocked = self.lock()?.1;
+        }
+        report.gitignore = self.ignore()?;
+        Ok(report)
+    }
+
+    /// Check the config for problems deserialization lets through, with `ssh_key` overriding
+    /// ssh.identityFile
+    /// return the warnings, or every problem as [`GitspaceError::Invalid`] if any is an error
+    pub fn validate(&self, ssh_key: Option<&Path>) -> Result<Vec<Problem>> {
+        fail_on_errors(self.config.validate(ssh_key))
+    }
+
+    /// Write lock.json from the commits every cloned repository is at
+    /// return it along with the repositories left out and why
+    pub fn lock(&self) -> Result<(Lockfile, Vec<(String, String)>)> {
+        let (lockfile, unlocked) = self.config.lock();
+        lockfile.write(&self.lock_path())?;
+        Ok((lockfile, unlocked))
+    }
+
+    /// Status of every repository matching `repos` (all of them when empty)
+    pub fn status(&self, repos: &[String]) -> Vec<RepoStatus> {
+        self.config.status(repos)
+    }
+
+    /// Names in `repos` that match no repository in the config
+    pub fn unknown_repos<'a>(&self, repos: &'a [String]) -> Vec<&'a str> {
+        self.config.unknown_repos(repos)
+    }
+
+    /// Names `--repo` accepts, for shell completion
+    pub fn repo_names(&self) -> Vec<String> {
+        self.config.repo_names()
+    }
+
+    /// Run `command` in every repository matching `repos` (all of them when empty)
+    pub fn exec(
+        &self,
+        repos: &[String],
+        command: &str,
+        options: &ExecOptions,
+    ) -> Vec<(String, ExecStatus)> {
+        exec::exec(&self.config.repo_dirs(repos), command, options)
+    }
+
+    /// Symlink every repository into `paths.symlinks`, the workspace root by default
+    pub fn symlink(&self, options: &SymlinkOptions) -> Result<SymlinkReport> {
+        self.config.write_symlinks(
+            options.mode.unwrap_or_else(|| self.config.symlink_mode()),
+            options
+                .on_conflict
+                .unwrap_or_else(|| self.config.symlink_policy()),
+        )
+    }
+
+    /// Sourceable aliases that `cd` into every repository
+    pub fn aliases(&self, shell: Shell) -> Result<String> {
+        Ok(alias::render(shell, &self.config.aliases()?))
+    }
+
+    /// Update the managed block of .gitignore in the workspace root
+    /// return its path when anything changed
+    pub fn ignore(&self) -> Result<Option<PathBuf>> {
+        self.config.write_gitignore()
+    }
+
+    /// Trash that `clean` moves removed paths into
+    pub fn trash(&self) -> Trash {
+        self.config.trash()
+    }
+
+    /// Links that differ from what config.json expects
+    pub fn check_symlinks(&self, options: &SymlinkOptions) -> Result<Vec<(String, LinkDrift)>> {
+        self.config
+            .check_symlinks(options.mode.unwrap_or_else(|| self.config.symlink_mode()))
+    }
+
+    /// Fix drifted links; [`Workspace::check_symlinks`] finds the drift that couldn't be fixed
+    pub fn repair_symlinks(&self, options: &SymlinkOptions) -> Result<SymlinkReport> {
+        self.config.repair_symlinks(
+            options.mode.unwrap_or_else(|| self.config.symlink_mode()),
+            options
+                .on_conflict
+                .unwrap_or_else(|| self.config.symlink_policy()),
+        )
+    }
+
+    /// Move `target` to the trash; repositories with unsaved work are only moved when `force` is set
+    pub fn clean(&self, target: CleanTarget, force: bool) -> Result<CleanReport> {
+        match target {
+            CleanTarget::Space => self.config.rm_space(force),
+            CleanTarget::Config => self.config.rm_config(),
+            CleanTarget::Repositories => self.config.rm_repositories(force),
+            CleanTarget::Symlinks => self.config.rm_symlinks(),
+        }
+    }
+}
+
+/// Nearest directory at or above `start` that contains `.space/config.json` (or .yaml, .yml, .toml)
+pub fn find_root(start: &Path) -> Option<PathBuf> {
+    start
+        .ancestors()
+        .find(|dir| find_config(dir).is_some())
+        .map(Path::to_path_buf)
+}
+
+/// The config file of the workspace rooted at `root`; JSON wins when there are several
+pub fn find_config(root: &Path) -> Option<PathBuf> {
+    CONFIG_FILES
+        .iter()
+        .map(|name| root.join(GITSPACE).join(name))
+        .find(|path| path.is_file())
+}
+
+/// `problems` when they're all warnings, or every one of them as [`GitspaceError::Invalid`]
+fn fail_on_errors(problems: Vec<Problem>) -> Result<Vec<Problem>> {
+    if problems
+        .iter()
+        .any(|problem| problem.severity == Severity::Error)
+    {
+        return Err(GitspaceError::Invalid(problems));
+    }
+    Ok(problems)
+}
+
+/// Nearest directory at or above `start` that contains `.space`, even when `clean` moved
+/// config.json to the trash
+pub fn find_space_root(start: &Path) -> Option<PathBuf> {
+    start
+        .ancestors()
+        .find(|dir| dir.join(GITSPACE).is_dir())
+        .map(Path::to_path_buf)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::{ConfigTemplate, CONFIG};
+    use crate::symlinks::SymlinkStatus;
+    use std::fs::{create_dir_all, write};
+    use tempfile::TempDir;
+
+    #[test]
+    fn open_reads_config_and_locates_lockfile() {
+        let dir = TempDir::new().unwrap();
+        let config_path = dir.path().join("config.json");
+        write(&config_path, Config::default().to_str()).unwrap();
+
+        let workspace = Workspace::open(&config_path).unwrap();
+        assert_eq!(
+            workspace.config().repositories,
+            Config::default().repositories
+        );
+        assert_eq!(workspace.root(), dir.path());
+        assert_eq!(workspace.lock_path(), dir.path().join("lock.json"));
+    }
+
+    #[test]
+    fn discover_walks_up_to_the_workspace_root() {
+        let dir = TempDir::new().unwrap();
+        let space = dir.path().join(GITSPACE);
+        create_dir_all(space.join("repositories/cli-gitspace/src")).unwrap();
+        write(space.join(CONFIG), Config::default().to_str()).unwrap();
+
+        let nested = space.join("repositories/cli-gitspace/src");
+        let workspace = Workspace::discover_from(&nested).unwrap();
+        assert_eq!(workspace.root(), dir.path());
+        assert_eq!(
+            workspace
+                .config()
+                .get_path_as_string(&PathType::Repositories),
+            space.join("repositories").to_string_lossy()
+        );
+
+        let outside = TempDir::new().unwrap();
+        assert!(matches!(
+            Workspace::discover_from(outside.path()),
+            Err(GitspaceError::NotAWorkspace(_))
+        ));
+    }
+
+    #[test]
+    fn only_init_creates_the_layout() {
+        let dir = TempDir::new().unwrap();
+        assert!(matches!(
+            Workspace::at(dir.path()),
+            Err(GitspaceError::NotAWorkspace(_))
+        ));
+        assert!(!dir.path().join(GITSPACE).exists());
+
+        let workspace = Workspace::init(dir.path()).unwrap();
+        assert!(dir.path().join(GITSPACE).join("repositories").is_dir());
+        assert_eq!(
+            workspace.config_path(),
+            dir.path().join(GITSPACE).join(CONFIG)
+        );
+        assert!(Workspace::at(dir.path()).is_ok());
+    }
+
+    #[test]
+    fn yaml_config_converts_to_toml() {
+        let dir = TempDir::new().unwrap();
+        let space = dir.path().join(GITSPACE);
+        Workspace::init_with_format(dir.path(), ConfigFormat::Yaml).unwrap();
+        assert!(space.join("config.yaml").is_file());
+
+        let workspace = Workspace::discover_from(dir.path()).unwrap();
+        assert_eq!(workspace.config_path(), space.join("config.yaml"));
+        assert_eq!(
+            workspace.config().repositories,
+            Config::default().repositories
+        );
+
+        let converted = workspace.convert(ConfigFormat::Toml).unwrap();
+        assert_eq!(converted, space.join("config.toml"));
+        assert!(!space.join("config.yaml").exists());
+        assert_eq!(workspace.trash().list().unwrap()[0].target, "config.yaml");
+
+        let workspace = Workspace::at(dir.path()).unwrap();
+        assert_eq!(workspace.config_path(), converted);
+        assert_eq!(
+            workspace.config().get_path_as_string(&PathType::Config),
+            converted.to_string_lossy()
+        );
+    }
+
+    #[test]
+    fn clean_refuses_to_remove_unsaved_work_unless_forced() {
+        let (dir, _, _) = crate::git::tests::upstream_and_clone();
+        let workspace = Workspace::init(dir.path()).unwrap();
+        let repositories = dir.path().join(GITSPACE).join("repositories");
+        std::fs::rename(dir.path().join("local"), repositories.join("local")).unwrap();
+        write(repositories.join("local/scratch.md"), "untracked").unwrap();
+
+        let refused = workspace.clean(CleanTarget::Repositories, false);
+        assert!(
+            matches!(refused, Err(GitspaceError::UnsavedWork(ref unsaved)) if unsaved[0].0 == "local")
+        );
+        assert!(repositories.join("local").exists());
+
+        workspace.clean(CleanTarget::Repositories, true).unwrap();
+        assert!(!repositories.exists());
+
+        workspace.trash().restore(None).unwrap();
+        assert!(repositories.join("local/scratch.md").exists());
+    }
+
+    #[test]
+    fn cleaned_space_is_restorable_without_its_config() {
+        let dir = TempDir::new().unwrap();
+        let workspace = Workspace::init(dir.path()).unwrap();
+        workspace.clean(CleanTarget::Space, false).unwrap();
+        assert!(Workspace::at(dir.path()).is_err());
+
+        let nested = dir.path().join("nested");
+        create_dir_all(&nested).unwrap();
+        let root = find_space_root(&nested).unwrap();
+        let entry = Trash::at(root).restore(None).unwrap();
+        assert_eq!(entry.paths.len(), 2);
+        assert!(Workspace::at(dir.path()).is_ok());
+    }
+
+    #[test]
+    #[cfg(unix)]
+    fn clean_only_removes_symlinks_gitspace_created() {
+        let dir = TempDir::new().unwrap();
+        let workspace = Workspace::init(dir.path()).unwrap();
+        workspace.symlink(&SymlinkOptions::default()).unwrap();
+        std::os::unix::fs::symlink(dir.path().join(GITSPACE), dir.path().join(".env")).unwrap();
+        // Repointed by hand, so no longer gitspace's to remove
+        std::fs::remove_file(dir.path().join("cli-ftr")).unwrap();
+        std::os::unix::fs::symlink(dir.path(), dir.path().join("cli-ftr")).unwrap();
+
+        let report = workspace.clean(CleanTarget::Symlinks, false).unwrap();
+        let kept: Vec<_> = report.kept.iter().map(|(path, _)| path.clone()).collect();
+        assert_eq!(
+            kept,
+            vec![dir.path().join("cli-ftr"), dir.path().join(".env")]
+        );
+        assert!(!dir.path().join("cli-gitspace").is_symlink());
+        assert!(dir.path().join("cli-ftr").is_symlink());
+        assert!(dir.path().join(".env").is_symlink());
+        assert!(!workspace.config().symlink_manifest_path().exists());
+
+        workspace
+            .trash()
+            .restore(Some(&report.trashed.unwrap().id))
+            .unwrap();
+        assert!(dir.path().join("cli-gitspace").is_symlink());
+        assert!(workspace.config().symlink_manifest_path().exists());
+    }
+
+    #[test]
+    #[cfg(unix)]
+    fn symlink_applies_the_conflict_policy() {
+        let dir = TempDir::new().unwrap();
+        let workspace = Workspace::init(dir.path()).unwrap();
+        create_dir_all(dir.path().join("cli-ftr")).unwrap();
+
+        assert!(matches!(
+            workspace.symlink(&SymlinkOptions::default()),
+            Err(GitspaceError::Filesystem { .. })
+        ));
+        let skipped = workspace
+            .symlink(&SymlinkOptions {
+                on_conflict: Some(ConflictPolicy::Skip),
+                ..Default::default()
+            })
+            .unwrap();
+        assert_eq!(skipped.links[0].1, SymlinkStatus::Unchanged);
+        assert!(matches!(skipped.links[1].1, SymlinkStatus::Conflict(_)));
+
+        let backed_up = workspace
+            .symlink(&SymlinkOptions {
+                on_conflict: Some(ConflictPolicy::Backup),
+                ..Default::default()
+            })
+            .unwrap();
+        let backup = dir.path().join("cli-ftr.bak");
+        assert_eq!(
+            backed_up.links[1].1,
+            SymlinkStatus::BackedUp(backup.to_path_buf())
+        );
+        assert!(backup.is_dir());
+        assert!(dir.path().join("cli-ftr").is_symlink());
+
+        std::fs::remove_file(dir.path().join("cli-gitspace")).unwrap();
+        std::os::unix::fs::symlink(&backup, dir.path().join("cli-gitspace")).unwrap();
+        let replaced = workspace
+            .symlink(&SymlinkOptions {
+                on_conflict: Some(ConflictPolicy::Replace),
+                ..Default::default()
+            })
+            .unwrap();
+        assert_eq!(replaced.links[0].1, SymlinkStatus::Replaced);
+        assert_eq!(replaced.links[1].1, SymlinkStatus::Unchanged);
+    }
+
+    #[test]
+    #[cfg(unix)]
+    fn symlink_modes_switch_between_links_and_copies() {
+        let (dir, _, _) = crate::git::tests::upstream_and_clone();
+        let workspace = Workspace::init(dir.path()).unwrap();
+        let repositories = dir.path().join(GITSPACE).join("repositories");
+        std::fs::rename(dir.path().join("local"), repositories.join("cli-gitspace")).unwrap();
+        let mut config: serde_json::Value =
+            serde_json::from_str(&workspace.config().to_str()).unwrap();
+        config["repositories"].as_array_mut().unwrap().truncate(1);
+        write(workspace.config_path(), config.to_string()).unwrap();
+        let workspace = Workspace::at(dir.path()).unwrap();
+        let link = dir.path().join("cli-gitspace");
+        let mode = |mode| SymlinkOptions {
+            mode: Some(mode),
+            ..Default::default()
+        };
+
+        workspace.symlink(&mode(LinkMode::Relative)).unwrap();
+        assert_eq!(
+            link.read_link().unwrap(),
+            Path::new(".space/repositories/cli-gitspace")
+        );
+
+        let copied = workspace.symlink(&mode(LinkMode::Copy)).unwrap();
+        assert_eq!(copied.links[0].1, SymlinkStatus::Replaced);
+        assert!(!link.is_symlink());
+        assert!(link.join("README.md").is_file());
+        // Copies that still match the clone are left alone, so nothing piles up in the trash
+        let trashed = || workspace.trash().list().unwrap().len();
+        let kept = workspace.symlink(&mode(LinkMode::Copy)).unwrap();
+        assert_eq!(kept.links[0].1, SymlinkStatus::Unchanged);
+        let repaired = workspace.repair_symlinks(&mode(LinkMode::Copy)).unwrap();
+        assert!(repaired.trashed.is_empty());
+        assert_eq!(trashed(), 0);
+
+        write(link.join("README.md"), "edited in the copy").unwrap();
+        assert_eq!(
+            workspace.check_symlinks(&mode(LinkMode::Copy)).unwrap(),
+            vec![("cli-gitspace".to_string(), LinkDrift::Outdated)]
+        );
+        let refreshed = workspace.symlink(&mode(LinkMode::Copy)).unwrap();
+        assert_eq!(refreshed.links[0].1, SymlinkStatus::Refreshed);
+        assert_eq!(refreshed.trashed.len(), 1);
+        assert_eq!(trashed(), 1);
+        assert_eq!(
+            std::fs::read_to_string(link.join("README.md")).unwrap(),
+            "one"
+        );
+        std::fs::remove_dir_all(&link).unwrap();
+        workspace.trash().restore(None).unwrap();
+        assert_eq!(
+            std::fs::read_to_string(link.join("README.md")).unwrap(),
+            "edited in the copy"
+        );
+
+        workspace.clean(CleanTarget::Symlinks, false).unwrap();
+        assert!(!link.exists());
+        assert!(repositories.join("cli-gitspace/README.md").is_file());
+    }
+
+    #[test]
+    #[cfg(unix)]
+    fn check_reports_drift_that_repair_fixes() {
+        let dir = TempDir::new().unwrap();
+        let workspace = Workspace::init(dir.path()).unwrap();
+        let repositories = dir.path().join(GITSPACE).join("repositories");
+        create_dir_all(repositories.join("cli-gitspace")).unwrap();
+        create_dir_all(repositories.join("cli-ftr")).unwrap();
+        let options = SymlinkOptions::default();
+        assert_eq!(workspace.check_symlinks(&options).unwrap().len(), 2);
+
+        workspace.symlink(&options).unwrap();
+        assert!(workspace.check_symlinks(&options).unwrap().is_empty());
+
+        std::fs::remove_file(dir.path().join("cli-ftr")).unwrap();
+        std::os::unix::fs::symlink(&repositories, dir.path().join("cli-ftr")).unwrap();
+        std::fs::rename(
+            repositories.join("cli-gitspace"),
+            repositories.join("renamed"),
+        )
+        .unwrap();
+        let drift = workspace.check_symlinks(&options).unwrap();
+        assert!(matches!(drift[0].1, LinkDrift::Dangling(_)));
+        assert_eq!(
+            drift[1].1,
+            LinkDrift::PointsElsewhere(repositories.to_path_buf())
+        );
+
+        // The dangling link can't be fixed until cli-gitspace is cloned again
+        let repaired = workspace.repair_symlinks(&options).unwrap();
+        assert_eq!(repaired.removed.len(), 2);
+        let left = workspace.check_symlinks(&options).unwrap();
+        assert_eq!(left.len(), 1);
+        assert_eq!(left[0].0, "cli-gitspace");
+        std::fs::rename(
+            repositories.join("renamed"),
+            repositories.join("cli-gitspace"),
+        )
+        .unwrap();
+        workspace.repair_symlinks(&options).unwrap();
+        assert!(workspace.check_symlinks(&options).unwrap().is_empty());
+    }
+
+    #[test]
+    #[cfg(unix)]
+    fn repair_leaves_symlinks_gitspace_didnt_create_to_the_conflict_policy() {
+        let dir = TempDir::new().unwrap();
+        let workspace = Workspace::init(dir.path()).unwrap();
+        let repositories = dir.path().join(GITSPACE).join("repositories");
+        create_dir_all(repositories.join("cli-gitspace")).unwrap();
+        create_dir_all(repositories.join("cli-ftr")).unwrap();
+        let link = dir.path().join("cli-ftr");
+        std::os::unix::fs::symlink(&repositories, &link).unwrap();
+
+        let options = SymlinkOptions::default();
+        workspace.repair_symlinks(&options).unwrap();
+        assert_eq!(
+            workspace.check_symlinks(&options).unwrap(),
+            vec![(
+                "cli-ftr".to_string(),
+                LinkDrift::PointsElsewhere(repositories.to_path_buf())
+            )]
+        );
+        assert_eq!(link.read_link().unwrap(), repositories);
+
+        let backup = SymlinkOptions {
+            on_conflict: Some(ConflictPolicy::Backup),
+            ..Default::default()
+        };
+        workspace.repair_symlinks(&backup).unwrap();
+        assert!(workspace.check_symlinks(&backup).unwrap().is_empty());
+        assert_eq!(
+            dir.path().join("cli-ftr.bak").read_link().unwrap(),
+            repositories
+        );
+    }
+
+    #[test]
+    #[cfg(unix)]
+    fn symlink_follows_the_loaded_config_paths() {
+        let dir = TempDir::new().unwrap();
+        let mut config: serde_json::Value =
+            serde_json::from_str(&Config::default().to_str()).unwrap();
+        config["paths"]["repositories"] = "clones".into();
+        config["paths"]["symlinks"] = "src".into();
+        config["repositories"] =
+            serde_json::json!([{ "namespace": "partner", "project": "billing" }]);
+        let config_path = dir.path().join("gitspace.json");
+        write(&config_path, config.to_string()).unwrap();
+
+        let workspace = Workspace::open(&config_path).unwrap();
+        workspace.symlink(&SymlinkOptions::default()).unwrap();
+        assert_eq!(
+            dir.path().join("src/billing").read_link().unwrap(),
+            dir.path().join(GITSPACE).join("clones/billing")
+        );
+        assert!(!dir.path().join("cli-gitspace").exists());
+
+        config["paths"]["symlinks"] = "../outside".into();
+        write(&config_path, config.to_string()).unwrap();
+        let escaping = Workspace::open(&config_path).unwrap();
+        assert!(matches!(
+            escaping.symlink(&SymlinkOptions::default()),
+            Err(GitspaceError::Config(_))
+        ));
+    }
+
+    #[test]
+    #[cfg(unix)]
+    fn relative_roots_are_anchored_once() {
+        let cwd = current_dir().unwrap();
+        let dir = TempDir::new_in(&cwd).unwrap();
+        let relative = dir.path().strip_prefix(&cwd).unwrap();
+        Workspace::init(relative).unwrap();
+        let repositories = dir.path().join(GITSPACE).join("repositories");
+        create_dir_all(repositories.join("cli-gitspace")).unwrap();
+        create_dir_all(repositories.join("cli-ftr")).unwrap();
+
+        // eg. `gitspace -w ..` from a subdirectory
+        let workspace = Workspace::at(relative.join(GITSPACE).join("..")).unwrap();
+        assert_eq!(workspace.root(), dir.path());
+        let absolute = SymlinkOptions {
+            mode: Some(LinkMode::Absolute),
+            ..Default::default()
+        };
+        workspace.symlink(&absolute).unwrap();
+        assert_eq!(
+            dir.path().join("cli-ftr").read_link().unwrap(),
+            repositories.join("cli-ftr")
+        );
+        assert!(workspace.config().symlink_manifest_path().is_file());
+        assert!(workspace.check_symlinks(&absolute).unwrap().is_empty());
+        assert!(workspace
+            .aliases(Shell::Zsh)
+            .unwrap()
+            .contains(&repositories.join("cli-ftr").display().to_string()));
+
+        workspace.clean(CleanTarget::Config, false).unwrap();
+        assert!(!dir.path().join(GITSPACE).join(CONFIG).exists());
+    }
+
+    #[test]
+    fn sync_stops_before_cloning_without_a_key() {
+        let dir = TempDir::new().unwrap();
+        let workspace = Workspace::init(dir.path()).unwrap();
+        let mut config: serde_json::Value =
+            serde_json::from_str(&workspace.config().to_str()).unwrap();
+        config["ssh"]["identityFile"] = "/nonexistent/id_rsa".into();
+        write(workspace.config_path(), config.to_string()).unwrap();
+        let workspace = Workspace::at(dir.path()).unwrap();
+        let missing_field = |options: &SyncOptions| match workspace.sync(options) {
+            Err(GitspaceError::Invalid(problems)) => problems
+                .iter()
+                .find(|problem| problem.severity == Severity::Error)
+                .map(|problem| problem.field.to_string()),
+            _ => None,
+        };
+
+        assert_eq!(
+            missing_field(&SyncOptions::default()).as_deref(),
+            Some("ssh.identityFile")
+        );
+        let options = SyncOptions {
+            key_path: Some(dir.path().join("missing_key")),
+            ..Default::default()
+        };
+        assert_eq!(missing_field(&options).as_deref(), Some("--ssh-key"));
+        assert!(!workspace.lock_path().exists());
+        // `config validate` still only warns, since sync may be given --ssh-key
+        assert_eq!(workspace.validate(None).unwrap().len(), 1);
+    }
+
+    #[test]
+    fn clean_target_falls_back_to_repositories() {
+        assert_eq!(CleanTarget::from_arg("l"), CleanTarget::Symlinks);
+        assert_eq!(CleanTarget::from_arg("space"), CleanTarget::Space);
+        assert_eq!(CleanTarget::from_arg("all"), CleanTarget::Repositories);
+    }
+}